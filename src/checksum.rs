@@ -0,0 +1,29 @@
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::prelude::*;
+
+/**
+ * @param data to hash
+ * @return the hex sha256 of data
+ */
+pub fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+ * @param path of a file
+ * @return the hex sha256 of the file, None if it can't be read
+ */
+pub fn sha256_file(path: &str) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 65536];
+    loop {
+        let read = file.read(&mut buf).ok()?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Some(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
@@ -0,0 +1,178 @@
+use issueretriever::{TuleapRef, ValueMapping};
+use serde_json::Value;
+use tuleapclient::TuleapClient;
+
+/**
+ * @param field the Json value of a tracker field
+ * @return the labels of the values the field allows, empty if it is not a list
+ */
+fn allowed_values(field: &Value) -> Vec<String> {
+    match field["values"].as_array() {
+        Some(values) => values.iter().filter_map(|v| v["label"].as_str()).map(String::from).collect(),
+        None => Vec::new()
+    }
+}
+
+/**
+ * @param tracker the structure of the tracker
+ * @param semantic of the tracker, like status
+ * @param fallback the field when the tracker doesn't define the semantic
+ * @return the id of the field
+ */
+fn semantic_field<'a>(tracker: &'a Value, semantic: &str, fallback: Option<&'a Value>) -> Option<u64> {
+    tracker["semantics"][semantic]["field_id"].as_u64().or(fallback.and_then(|f| f["field_id"].as_u64()))
+}
+
+/**
+ * @param mappings of the values of a field
+ * @return the mappings as in the config
+ */
+fn values_config(mappings: &[ValueMapping]) -> Value {
+    json!(mappings.iter().map(|m| {
+        let mut value = json!({
+            "value": match m.value {
+                TuleapRef::Id(id) => json!(id),
+                TuleapRef::Label(ref label) => json!(label)
+            },
+            "closed": m.closed
+        });
+        if let Some(ref label) = m.label {
+            value["label"] = json!(label);
+        }
+        value
+    }).collect::<Vec<Value>>())
+}
+
+/**
+ * Find the fields read by the conversion, by id, with the mapping of their values
+ * @param tracker the structure of the tracker
+ * @return the fields section of a tracker
+ */
+fn detect_fields(tracker: &Value) -> Value {
+    let empty = Vec::new();
+    let fields = tracker["fields"].as_array().unwrap_or(&empty);
+    let by_label = |label: &str| fields.iter().find(|f| f["label"] == label);
+    let mut result = json!({});
+    let detected = [
+        ("severity", by_label("Severity").and_then(|f| f["field_id"].as_u64())),
+        ("status", semantic_field(tracker, "status", by_label("Status"))),
+        ("description", semantic_field(tracker, "description", by_label("Original Submission"))),
+        ("attachments", fields.iter().find(|f| f["type"] == "file").and_then(|f| f["field_id"].as_u64())),
+        ("assignee", semantic_field(tracker, "contributor", by_label("Assigned to")))
+    ];
+    for &(name, id) in detected.iter() {
+        if let Some(id) = id {
+            result[name] = json!(id);
+        }
+    }
+    if let Some(severity) = result["severity"].as_u64() {
+        result["severity_values"] = values_config(&ValueMapping::default_severities(false, tracker, &TuleapRef::Id(severity)));
+    }
+    if let Some(status) = result["status"].as_u64() {
+        // Values outside the status semantic are closed
+        let open: Vec<u64> = tracker["semantics"]["status"]["value_ids"].as_array()
+            .map_or(Vec::new(), |v| v.iter().filter_map(|v| v.as_u64()).collect());
+        let values = fields.iter().find(|f| f["field_id"] == status).and_then(|f| f["values"].as_array());
+        let mappings: Vec<ValueMapping> = match values {
+            Some(values) if !open.is_empty() => values.iter().filter_map(|v| {
+                let id = v["id"].as_u64()?;
+                Some(ValueMapping {
+                    value: TuleapRef::Id(id),
+                    label: if v["label"] == "Declined" { Some(String::from("invalid")) } else { None },
+                    closed: !open.contains(&id)
+                })
+            }).collect(),
+            _ => ValueMapping::default_statuses(false).into_iter().map(|m| ValueMapping {
+                value: m.value.resolve_value(tracker, &TuleapRef::Id(status)),
+                ..m
+            }).collect()
+        };
+        result["status_values"] = values_config(&mappings);
+    }
+    result
+}
+
+/**
+ * Print the trackers of a project and their fields, and build a starter mapping
+ * @param tc the tuleap client
+ * @param project the id of the tuleap project
+ * @return the trackers section of a config, to complete with gitlab projects
+ */
+pub fn discover(tc: &mut TuleapClient, project: u64) -> Value {
+    let mut trackers: Vec<Value> = Vec::new();
+    for t in tc.get_project_trackers(project) {
+        let id = t["id"].as_u64().unwrap_or(0);
+        let tracker = tc.get_tracker(id);
+        let name = tracker["label"].as_str().unwrap_or("");
+        println!("Tracker {} ({}):", id, name);
+        let empty = Vec::new();
+        let fields = tracker["fields"].as_array().unwrap_or(&empty);
+        for f in fields {
+            let values: Vec<String> = f["values"].as_array().map_or(Vec::new(), |v| v.iter().map(|v| {
+                format!("{} ({})", v["label"].as_str().unwrap_or(""), v["id"])
+            }).collect());
+            println!("  - {} {} ({}){}", f["field_id"], f["label"].as_str().unwrap_or(""), f["type"].as_str().unwrap_or(""),
+                     if values.is_empty() { String::new() } else { format!(": {}", values.join(", ")) });
+        }
+        // Without a Platform field, guess the first multiple choice field, or list field
+        let platform = fields.iter().find(|f| f["label"] == "Platform")
+            .or_else(|| fields.iter().find(|f| f["type"] == "msb" && !allowed_values(f).is_empty()))
+            .or_else(|| fields.iter().find(|f| !allowed_values(f).is_empty()));
+        let mut definition = json!({
+            "id": id,
+            "extra_labels": [format!("type::{}", tracker["item_name"].as_str().unwrap_or(name)).to_lowercase()],
+            "routing": [],
+            "fields": detect_fields(&tracker)
+        });
+        if let Some(platform) = platform {
+            definition["platform_field"] = platform["field_id"].clone();
+            let empty = Vec::new();
+            let projects: Vec<Value> = platform["values"].as_array().unwrap_or(&empty).iter().map(|v| json!({
+                "platform": v["id"],
+                "gitlab_id": ""
+            })).collect();
+            definition["projects"] = json!(projects);
+        }
+        trackers.push(definition);
+    }
+    json!({ "trackers": trackers })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detect_fields;
+
+    #[test]
+    fn detect_fields_by_semantics_and_ids() {
+        let tracker = json!({"id": 101, "fields": [
+            {"field_id": 1, "label": "Severity", "type": "sb", "values": [
+                {"id": 10, "label": "1 - Ordinary"}, {"id": 12, "label": "2"}, {"id": 19, "label": "9 - Critical"}
+            ]},
+            {"field_id": 2, "label": "Etat", "type": "sb", "values": [
+                {"id": 20, "label": "Nouveau"}, {"id": 21, "label": "Declined"}, {"id": 22, "label": "Fait"}
+            ]},
+            {"field_id": 3, "label": "Details", "type": "text"},
+            {"field_id": 4, "label": "Files", "type": "file"},
+            {"field_id": 5, "label": "Assigned to", "type": "sb"}
+        ], "semantics": {
+            "status": {"field_id": 2, "value_ids": [20]},
+            "description": {"field_id": 3}
+        }});
+        assert_eq!(detect_fields(&tracker), json!({
+            "severity": 1,
+            "status": 2,
+            "description": 3,
+            "attachments": 4,
+            "assignee": 5,
+            "severity_values": [
+                {"value": 10, "label": "S - Ordinary", "closed": false},
+                {"value": 19, "label": "S - Critical", "closed": false}
+            ],
+            "status_values": [
+                {"value": 20, "closed": false},
+                {"value": 21, "label": "invalid", "closed": true},
+                {"value": 22, "closed": true}
+            ]
+        }));
+    }
+}
@@ -0,0 +1,247 @@
+use chrono::Duration;
+use chrono::prelude::*;
+use issueretriever::TuleapRef;
+use serde_json::Value;
+
+/**
+ * Select the artifacts to migrate, every condition set must match
+ */
+pub struct ArtifactFilter {
+    pub ids: Vec<u64>,
+    pub id_min: Option<u64>,
+    pub id_max: Option<u64>,
+    // Labels of the status semantic
+    pub statuses: Vec<String>,
+    pub submitted_after: Option<DateTime<FixedOffset>>,
+    pub submitted_before: Option<DateTime<FixedOffset>>,
+    pub modified_after: Option<DateTime<FixedOffset>>,
+    pub modified_before: Option<DateTime<FixedOffset>>,
+    // Field and a value it must contain
+    pub fields: Vec<(TuleapRef, TuleapRef)>
+}
+
+impl ArtifactFilter {
+    /**
+     * @param date like 2017-07-21 or 2017-07-21T00:00:00+02:00
+     * @return the date, None if it can't be parsed
+     */
+    pub fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(date)
+            .or_else(|_| DateTime::parse_from_rfc3339(&*format!("{}T00:00:00+00:00", date)))
+            .ok()
+    }
+
+    /**
+     * @return if field values are needed to filter artifacts
+     */
+    pub fn needs_values(&self) -> bool {
+        !self.fields.is_empty()
+    }
+
+    /**
+     * Tuleap query language for the conditions tuleap can check (ids and dates),
+     * the others are checked by matches
+     * @return the expert query, empty if there is no such condition
+     */
+    pub fn query(&self) -> String {
+        let mut conditions: Vec<String> = Vec::new();
+        if !self.ids.is_empty() {
+            let ids: Vec<String> = self.ids.iter().map(|id| format!("@id = {}", id)).collect();
+            conditions.push(format!("({})", ids.join(" OR ")));
+        }
+        if let Some(id) = self.id_min {
+            conditions.push(format!("@id >= {}", id));
+        }
+        if let Some(id) = self.id_max {
+            conditions.push(format!("@id <= {}", id));
+        }
+        // Tuleap reads dates in its own timezone: the bounds are widened by a day and matches
+        // does the exact cut
+        let day = Duration::days(1);
+        let dates = [("@submitted_on >=", &self.submitted_after, -day), ("@submitted_on <=", &self.submitted_before, day),
+                     ("@last_update_date >=", &self.modified_after, -day), ("@last_update_date <=", &self.modified_before, day)];
+        for &(condition, date, margin) in dates.iter() {
+            if let Some(ref date) = *date {
+                let date = date.with_timezone(&Utc) + margin;
+                conditions.push(format!("{} \"{}\"", condition, date.format("%Y-%m-%d %H:%M")));
+            }
+        }
+        conditions.join(" AND ")
+    }
+
+    /**
+     * @param artifact from the tracker
+     * @return true if the artifact must be migrated
+     */
+    pub fn matches(&self, artifact: &Value) -> bool {
+        let id = artifact["id"].as_u64().unwrap_or(0);
+        if !self.ids.is_empty() && !self.ids.contains(&id) {
+            return false;
+        }
+        if self.id_min.map_or(false, |min| id < min) || self.id_max.map_or(false, |max| id > max) {
+            return false;
+        }
+        if !self.statuses.is_empty() {
+            let status = artifact["status"].as_str().unwrap_or("").to_lowercase();
+            if !self.statuses.iter().any(|s| s.to_lowercase() == status) {
+                return false;
+            }
+        }
+        let in_range = |date: &Value, after: &Option<DateTime<FixedOffset>>, before: &Option<DateTime<FixedOffset>>| {
+            if after.is_none() && before.is_none() {
+                return true;
+            }
+            match DateTime::parse_from_rfc3339(date.as_str().unwrap_or("")) {
+                Ok(date) => after.map_or(true, |a| date >= a) && before.map_or(true, |b| date <= b),
+                Err(_) => false
+            }
+        };
+        if !in_range(&artifact["submitted_on"], &self.submitted_after, &self.submitted_before)
+            || !in_range(&artifact["last_modified_date"], &self.modified_after, &self.modified_before) {
+            return false;
+        }
+        let values = artifact["values"].as_array();
+        self.fields.iter().all(|&(ref field, ref value)| {
+            values.map_or(false, |values| values.iter().any(|v| field.matches_field(v) && value.in_field(v)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArtifactFilter;
+    use issueretriever::TuleapRef;
+
+    fn filter() -> ArtifactFilter {
+        ArtifactFilter {
+            ids: Vec::new(),
+            id_min: None,
+            id_max: None,
+            statuses: Vec::new(),
+            submitted_after: None,
+            submitted_before: None,
+            modified_after: None,
+            modified_before: None,
+            fields: Vec::new()
+        }
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = filter();
+        assert_eq!(filter.query(), "");
+        assert!(filter.matches(&json!({"id": 12})));
+        assert!(!filter.needs_values());
+    }
+
+    #[test]
+    fn ids() {
+        let mut filter = filter();
+        filter.ids = vec![1, 2];
+        assert_eq!(filter.query(), "(@id = 1 OR @id = 2)");
+        assert!(filter.matches(&json!({"id": 2})));
+        assert!(!filter.matches(&json!({"id": 3})));
+    }
+
+    #[test]
+    fn id_range() {
+        let mut filter = filter();
+        filter.id_min = Some(10);
+        filter.id_max = Some(20);
+        assert_eq!(filter.query(), "@id >= 10 AND @id <= 20");
+        assert!(filter.matches(&json!({"id": 10})));
+        assert!(filter.matches(&json!({"id": 20})));
+        assert!(!filter.matches(&json!({"id": 9})));
+        assert!(!filter.matches(&json!({"id": 21})));
+    }
+
+    #[test]
+    fn ids_and_range() {
+        let mut filter = filter();
+        filter.ids = vec![5, 15];
+        filter.id_min = Some(10);
+        assert_eq!(filter.query(), "(@id = 5 OR @id = 15) AND @id >= 10");
+        assert!(filter.matches(&json!({"id": 15})));
+        assert!(!filter.matches(&json!({"id": 5})));
+    }
+
+    #[test]
+    fn parse_date() {
+        assert_eq!(ArtifactFilter::parse_date("2020-01-10").unwrap().to_rfc3339(), "2020-01-10T00:00:00+00:00");
+        assert_eq!(ArtifactFilter::parse_date("2020-01-10T10:00:00+02:00").unwrap().to_rfc3339(),
+                   "2020-01-10T10:00:00+02:00");
+        assert!(ArtifactFilter::parse_date("10/01/2020").is_none());
+    }
+
+    #[test]
+    fn dates_are_sent_in_utc_with_a_day_of_margin() {
+        let mut filter = filter();
+        filter.modified_after = ArtifactFilter::parse_date("2020-01-10T10:00:00+02:00");
+        filter.submitted_before = ArtifactFilter::parse_date("2020-01-10");
+        assert_eq!(filter.query(), "@submitted_on <= \"2020-01-11 00:00\" AND @last_update_date >= \"2020-01-09 08:00\"");
+    }
+
+    #[test]
+    fn dates_are_cut_exactly() {
+        let mut filter = filter();
+        filter.modified_after = ArtifactFilter::parse_date("2020-01-10T10:00:00+02:00");
+        let modified = |date: &str| json!({"id": 1, "submitted_on": "2019-01-01T00:00:00+00:00", "last_modified_date": date});
+        assert!(filter.matches(&modified("2020-01-10T09:30:00+01:00")));
+        assert!(filter.matches(&modified("2020-01-10T08:00:00+00:00")));
+        assert!(!filter.matches(&modified("2020-01-10T07:59:59+00:00")));
+        assert!(!filter.matches(&modified("2020-01-10T09:59:00+02:00")));
+        assert!(!filter.matches(&json!({"id": 1})));
+    }
+
+    #[test]
+    fn submitted_range() {
+        let mut filter = filter();
+        filter.submitted_after = ArtifactFilter::parse_date("2020-01-01");
+        filter.submitted_before = ArtifactFilter::parse_date("2020-02-01");
+        let submitted = |date: &str| json!({"id": 1, "submitted_on": date});
+        assert!(filter.matches(&submitted("2020-01-15T12:00:00+01:00")));
+        assert!(!filter.matches(&submitted("2019-12-31T23:00:00+00:00")));
+        assert!(!filter.matches(&submitted("2020-02-01T00:00:01+00:00")));
+    }
+
+    #[test]
+    fn statuses() {
+        let mut filter = filter();
+        filter.statuses = vec![String::from("Open")];
+        assert_eq!(filter.query(), "");
+        assert!(filter.matches(&json!({"id": 1, "status": "open"})));
+        assert!(!filter.matches(&json!({"id": 1, "status": "Closed"})));
+    }
+
+    #[test]
+    fn fields() {
+        let mut filter = filter();
+        filter.fields = vec![(TuleapRef::Label(String::from("Category")), TuleapRef::Label(String::from("Network"))),
+                             (TuleapRef::Id(100), TuleapRef::Id(1001))];
+        assert!(filter.needs_values());
+        assert_eq!(filter.query(), "");
+        let artifact = |category: &str, bind_value: u64| json!({"id": 1, "values": [
+            {"field_id": 99, "label": "Category", "values": [{"id": 990, "label": category}]},
+            {"field_id": 100, "label": "Priority", "values": [{"id": bind_value, "label": "High"}]}
+        ]});
+        assert!(filter.matches(&artifact("Network", 1001)));
+        assert!(!filter.matches(&artifact("Storage", 1001)));
+        assert!(!filter.matches(&artifact("Network", 1002)));
+        assert!(!filter.matches(&json!({"id": 1})));
+    }
+
+    #[test]
+    fn fields_and_dates_and_ids() {
+        let mut filter = filter();
+        filter.id_max = Some(50);
+        filter.modified_before = ArtifactFilter::parse_date("2020-01-10");
+        filter.fields = vec![(TuleapRef::Label(String::from("Category")), TuleapRef::Label(String::from("Network")))];
+        assert_eq!(filter.query(), "@id <= 50 AND @last_update_date <= \"2020-01-11 00:00\"");
+        let artifact = |id: u64, date: &str| json!({"id": id, "last_modified_date": date, "values": [
+            {"field_id": 99, "label": "Category", "values": [{"id": 990, "label": "Network"}]}
+        ]});
+        assert!(filter.matches(&artifact(50, "2020-01-09T23:59:59+00:00")));
+        assert!(!filter.matches(&artifact(51, "2020-01-09T23:59:59+00:00")));
+        assert!(!filter.matches(&artifact(50, "2020-01-10T00:00:01+00:00")));
+    }
+}
@@ -1,15 +1,31 @@
+use checksum::sha256_file;
+use chrono::prelude::*;
+use idmap::MappedIssue;
 use reqwest;
 use serde_json::{Value, from_str};
 use std::collections::HashMap;
 use std::fmt;
+use std::fs::{copy, create_dir_all, metadata};
+use std::path::Path;
 use std::panic;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
 
 /**
  * Represent a gitlab comment
  **/
+#[derive(Clone)]
 pub struct GitlabComment {
+    // Id of the tuleap changeset
+    pub changeset_id: u64,
     pub body: String,
-    pub created_at: String
+    pub created_at: String,
+    // Files added with this comment
+    pub attachments: Vec<String>
 }
 // Used for println!
 impl fmt::Display for GitlabComment {
@@ -18,10 +34,46 @@ impl fmt::Display for GitlabComment {
     }
 }
 
+/**
+ * Other projects of an issue, when the artifact lists several platforms
+ **/
+#[derive(Clone)]
+pub enum RelatedProjects {
+    // A short issue linking to the main one is created in each project
+    Links(Vec<String>),
+    // A full copy is created in each project, all linked together
+    Copies(Vec<String>)
+}
+
+impl RelatedProjects {
+    /**
+     * @return the other projects
+     */
+    pub fn projects(&self) -> &Vec<String> {
+        match *self {
+            RelatedProjects::Links(ref projects) | RelatedProjects::Copies(ref projects) => projects
+        }
+    }
+}
+
+/**
+ * @param index of the file in GitlabIssue::inline_files
+ * @return the text replaced by the file in descriptions and comments
+ */
+pub fn inline_file_marker(index: usize, url_only: bool) -> String {
+    if url_only {
+        format!("{{{{tuleap-file-url:{}}}}}", index)
+    } else {
+        format!("{{{{tuleap-file:{}}}}}", index)
+    }
+}
+
 /**
  * Represent a gitlab issue
  **/
+#[derive(Clone)]
 pub struct GitlabIssue {
+    pub tuleap_id: u64,
     pub title: String,
     pub closed: bool,
     pub description: String,
@@ -30,7 +82,20 @@ pub struct GitlabIssue {
     pub project_url: String,
     pub created_at: String,
     pub comments: Vec<GitlabComment>,
-    pub attachments: Vec<String>
+    // All files, those not added with a comment are in the description
+    pub attachments: Vec<String>,
+    // Files referenced in the description or comments (path, tuleap url)
+    pub inline_files: Vec<(String, String)>,
+    // Attachments which could not be downloaded from tuleap, mentioned in the description
+    pub failed_downloads: Vec<String>,
+    // Tuleap artifacts linked to this one
+    pub linked_artifacts: Vec<u64>,
+    pub related: RelatedProjects,
+    // Filled tuleap fields not migrated
+    pub skipped_fields: Vec<String>,
+    // Users and platforms without a gitlab equivalent
+    pub unmapped: Vec<String>,
+    pub retrieve_time: Duration
 }
 // Used for println!
 impl fmt::Display for GitlabIssue {
@@ -39,6 +104,54 @@ impl fmt::Display for GitlabIssue {
     }
 }
 
+impl GitlabIssue {
+    /**
+     * @param mapped the gitlab issue and what was already posted on it
+     * @return the comments not posted yet
+     */
+    pub fn new_comments(&self, mapped: &MappedIssue) -> Vec<&GitlabComment> {
+        match mapped.last_changeset {
+            Some(last) => self.comments.iter().filter(|c| c.changeset_id > last).collect(),
+            // Maps written before changesets were recorded
+            None => self.comments.iter().skip(mapped.comments).collect()
+        }
+    }
+}
+
+/**
+ * Outcome of posting an issue
+ **/
+#[derive(Default)]
+pub struct PostResult {
+    pub iid: Option<u64>,
+    pub web_url: String,
+    // Attachments which failed to upload, they are only mentioned
+    pub failed_attachments: Vec<String>,
+    pub failed_comments: usize,
+    // Last tuleap changeset posted as a comment
+    pub last_changeset: Option<u64>,
+    // Files uploaded to gitlab, the others are archive links or mentions
+    pub uploads: usize,
+    // Issues created in related projects
+    pub related_urls: Vec<String>,
+    pub post_time: Duration
+}
+
+/**
+ * In which order issues are created on gitlab
+ **/
+#[derive(Clone, Copy)]
+pub enum CreationOrder {
+    // As soon as a worker is ready
+    Unordered,
+    // By tuleap creation date, so iids follow the original chronology
+    CreationDate,
+    // By artifact id, with iid == artifact id. Gaps are filled by placeholders that are
+    // deleted (or closed if delete_placeholders is false) once the issue is created. An issue
+    // needing more than max_gap placeholders fails (0 for no limit)
+    ArtifactId { delete_placeholders: bool, max_gap: u64 }
+}
+
 /**
  * client used to generate issues on gitlab
  **/
@@ -46,87 +159,781 @@ pub struct GitlabClient {
     client: reqwest::Client,
     gitlab_url: String,
     private_token: String,
+    // Next iid per project, known once gitlab ignored the iid we asked for
+    next_iids: Mutex<HashMap<String, u64>>,
+    // Names of existing labels per project or group
+    known_labels: Mutex<HashMap<String, Vec<String>>>,
+    // Markdown of files already uploaded, by project and sha256
+    uploads: Mutex<HashMap<(String, String), String>>,
+    // Bigger files are not uploaded (in bytes, 0 for no limit)
+    max_attachment_size: u64,
+    // Where bigger files are copied, and the url serving this directory
+    archive_dir: String,
+    archive_url: String
 }
 
 impl GitlabClient {
-    pub fn new(gitlab_url: String, private_token: String) -> GitlabClient {
+    /**
+     * @param gitlab_url the base url of gitlab
+     * @param private_token used for every request
+     * @param max_attachment_size maximum size of an upload in bytes, 0 for no limit
+     * @param archive_dir where files too big are copied, they are only mentioned if empty
+     * @param archive_url the url serving archive_dir, used to link files too big
+     */
+    pub fn new(gitlab_url: String, private_token: String, max_attachment_size: u64,
+               archive_dir: String, archive_url: String) -> GitlabClient {
         let client = reqwest::Client::new();
 
         GitlabClient {
             client: client,
             gitlab_url: gitlab_url,
-            private_token: private_token
+            private_token: private_token,
+            next_iids: Mutex::new(HashMap::new()),
+            known_labels: Mutex::new(HashMap::new()),
+            uploads: Mutex::new(HashMap::new()),
+            max_attachment_size: max_attachment_size,
+            archive_dir: archive_dir,
+            archive_url: archive_url
         }
     }
 
     /**
-     * Generate labels for each projects
+     * Create or update labels for each projects, or once for a group
+     * @param projects gitlab ids of the projects
+     * @param group id or path of the group, labels are created per project if empty
+     * @param labels from the config
      */
-    pub fn generate_labels(&self, project_map: HashMap<String, String>, labels: &Value) {
-        if !labels.is_array() {
+    pub fn generate_labels(&self, projects: &[String], group: &str, labels: &Value) {
+        let labels = match labels.as_array() {
+            Some(labels) => labels,
+            None => return
+        };
+        if !group.is_empty() {
+            self.sync_labels(&*GitlabClient::label_target(group, ""), labels);
             return;
         }
-        for (_, gitlab_id) in project_map {
-            for label in labels.as_array().unwrap() {
-                let url = format!("{}/api/v4/projects/{}/labels?private_token={}",
-                                 self.gitlab_url, gitlab_id, self.private_token);
-                // Generate first post
-                let mut post = HashMap::new();
-                let name = label["name"].as_str().unwrap_or("");
-                let color = label["color"].as_str().unwrap_or("");
-                post.insert("name", name);
-                post.insert("color", color);
-                info!("Generate new label: {}/{}", name, color);
+        for gitlab_id in projects {
+            self.sync_labels(&*GitlabClient::label_target("", gitlab_id), labels);
+        }
+    }
 
-                // Create issue and retrieve iid
-                let _ = self.client.post(&*url)
-                                   .json(&post)
-                                   .send().ok().expect("Failed to generate post");
+    /**
+     * @param group id or path of the group, if labels are at group level
+     * @param project id of the project
+     * @return where labels are stored for the API (groups/id or projects/id)
+     */
+    fn label_target(group: &str, project: &str) -> String {
+        if group.is_empty() {
+            return format!("projects/{}", project);
+        }
+        let encoded: String = form_urlencoded::byte_serialize(group.as_bytes()).collect();
+        format!("groups/{}", encoded)
+    }
+
+    /**
+     * Create missing labels and update the ones which changed
+     * @param target groups/id or projects/id
+     * @param labels from the config
+     */
+    fn sync_labels(&self, target: &str, labels: &[Value]) {
+        let existing = self.get_labels(target);
+        let mut names: Vec<String> = Vec::new();
+        for label in labels {
+            let name = label["name"].as_str().unwrap_or("");
+            let mut post = json!({
+                "name": name,
+                "color": label["color"].as_str().unwrap_or("#428BCA")
+            });
+            if let Some(description) = label["description"].as_str() {
+                post["description"] = json!(description);
+            }
+            // Priorities only exist for project labels
+            if label["priority"].is_u64() && target.starts_with("projects/") {
+                post["priority"] = label["priority"].clone();
+            }
+            names.push(String::from(name));
+            match existing.iter().find(|e| e["name"].as_str() == Some(name)) {
+                Some(current) => {
+                    let changed = post.as_object().unwrap().iter().any(|(k, v)| match (v.as_str(), current[k].as_str()) {
+                        (Some(v), Some(c)) => v.to_lowercase() != c.to_lowercase(),
+                        _ => *v != current[k]
+                    });
+                    if !changed {
+                        debug!("Label {} already up to date in {}", name, target);
+                        continue;
+                    }
+                    let url = format!("{}/api/v4/{}/labels/{}?private_token={}",
+                                      self.gitlab_url, target, current["id"], self.private_token);
+                    info!("Update label {} in {}", name, target);
+                    match self.client.put(&*url).json(&post).send() {
+                        Ok(ref res) if res.status().is_success() => {},
+                        _ => warn!("Failed to update label {} in {}", name, target)
+                    }
+                },
+                None => {
+                    let url = format!("{}/api/v4/{}/labels?private_token={}",
+                                      self.gitlab_url, target, self.private_token);
+                    info!("Generate new label {} in {}", name, target);
+                    match self.client.post(&*url).json(&post).send() {
+                        Ok(ref res) if res.status().is_success() => {},
+                        _ => warn!("Failed to create label {} in {}", name, target)
+                    }
+                }
             }
         }
+        names.extend(existing.iter().map(|e| String::from(e["name"].as_str().unwrap_or(""))));
+        self.known_labels.lock().unwrap().insert(String::from(target), names);
     }
 
+    /**
+     * Create labels used by issues which do not exist yet, with a default color
+     * @param issues to generate
+     * @param group id or path of the group, if labels are at group level
+     */
+    pub fn ensure_labels(&self, issues: &[GitlabIssue], group: &str) {
+        let mut known_labels = self.known_labels.lock().unwrap();
+        for issue in issues {
+            let mut projects = vec![issue.project_url.clone()];
+            match issue.related {
+                RelatedProjects::Links(ref p) | RelatedProjects::Copies(ref p) => projects.extend(p.iter().cloned())
+            }
+            for project in projects.iter().filter(|p| !p.is_empty()) {
+                let target = GitlabClient::label_target(group, &*project);
+                if !known_labels.contains_key(&target) {
+                    let existing = self.get_labels(&*target).iter()
+                                   .map(|e| String::from(e["name"].as_str().unwrap_or(""))).collect();
+                    known_labels.insert(target.clone(), existing);
+                }
+                let known = known_labels.get_mut(&target).unwrap();
+                for label in issue.labels.iter() {
+                    if known.contains(label) {
+                        continue;
+                    }
+                    let url = format!("{}/api/v4/{}/labels?private_token={}",
+                                      self.gitlab_url, target, self.private_token);
+                    let post = json!({
+                        "name": label,
+                        "color": "#428BCA"
+                    });
+                    info!("Generate undeclared label {} in {}", label, target);
+                    let _ = self.client.post(&*url).json(&post).send();
+                    known.push(label.clone());
+                }
+            }
+        }
+    }
 
     /**
-     * Generate a gitlab issue on a tracker from the API
+     * Retrieve all labels of a project or a group
+     * @param target groups/id or projects/id
+     * @return a vec of Json values from the API
+     */
+    fn get_labels(&self, target: &str) -> Vec<Value> {
+        let mut all_labels: Vec<Value> = Vec::new();
+        let mut page = 1;
+        loop {
+            let url = format!("{}/api/v4/{}/labels?private_token={}&per_page=100&page={}",
+                              self.gitlab_url, target, self.private_token, page);
+            let body = match self.client.get(&*url).send() {
+                Ok(mut req) => req.text().unwrap_or(String::new()),
+                Err(_) => break
+            };
+            let mut labels: Vec<Value> = from_str(&*body).unwrap_or(Vec::new());
+            if labels.is_empty() {
+                break;
+            }
+            all_labels.append(&mut labels);
+            page += 1;
+        }
+        all_labels
+    }
+
+    /**
+     * Generate gitlab issues with a pool of workers
+     * @param issues to generate
+     * @param workers number of issues posted in parallel
+     * @param order in which order issues are created. Except for Unordered, creations are serialized
+     * @return the result of each issue, in the same order as issues
+     */
+    pub fn generate_issues(&self, issues: &[GitlabIssue], workers: usize, order: CreationOrder) -> Vec<PostResult> {
+        let mut positions: Vec<usize> = (0..issues.len()).collect();
+        match order {
+            CreationOrder::Unordered => {},
+            CreationOrder::CreationDate => positions.sort_by_key(|p| DateTime::parse_from_rfc3339(&*issues[*p].created_at).ok()),
+            CreationOrder::ArtifactId { .. } => positions.sort_by_key(|p| issues[*p].tuleap_id)
+        }
+        let next = AtomicUsize::new(0);
+        // Index of the next issue allowed to be created when ordered
+        let turn = (Mutex::new(0), Condvar::new());
+        let mut results: Vec<PostResult> = (0..issues.len()).map(|_| PostResult::default()).collect();
+        let (tx, rx) = channel();
+
+        thread::scope(|s| {
+            for _ in 0..workers.max(1) {
+                let tx = tx.clone();
+                let positions = &positions;
+                let next = &next;
+                let turn = &turn;
+                s.spawn(move || {
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::SeqCst);
+                        if idx >= positions.len() {
+                            break;
+                        }
+                        let issue = &issues[positions[idx]];
+                        if let CreationOrder::Unordered = order {
+                            let _ = tx.send((positions[idx], self.generate_issue(issue)));
+                            continue;
+                        }
+                        // Uploads do not consume iids, so they are done before waiting our turn
+                        let started = Instant::now();
+                        let (description, failed) = self.upload_attachments(issue);
+                        let uploads = GitlabClient::uploads(&*description);
+                        let created = {
+                            let (ref lock, ref cvar) = *turn;
+                            let mut current = lock.lock().unwrap();
+                            while *current != idx {
+                                current = cvar.wait(current).unwrap();
+                            }
+                            let created = match order {
+                                CreationOrder::ArtifactId { delete_placeholders, max_gap } => {
+                                    let mut next_iids = self.next_iids.lock().unwrap();
+                                    self.create_issue_at_artifact_id(issue, description, &mut next_iids,
+                                                                     delete_placeholders, max_gap)
+                                },
+                                _ => self.create_issue(issue, description, None)
+                            };
+                            *current += 1;
+                            cvar.notify_all();
+                            created
+                        };
+                        // Related issues would take iids behind the back of the ordered creations
+                        let related = match order {
+                            CreationOrder::ArtifactId { .. } => false,
+                            _ => true
+                        };
+                        let result = self.complete_issue(issue, created, failed, uploads, related, started);
+                        let _ = tx.send((positions[idx], result));
+                    }
+                });
+            }
+            drop(tx);
+            for (position, result) in rx {
+                results[position] = result;
+            }
+        });
+        results
+    }
+
+    /**
+     * Create an issue whose iid is the tuleap artifact id
      * @param issue to generate
+     * @param description the description including attachments
+     * @param next_iids next iid of each project where the iid parameter is ignored
+     * @param delete_placeholders delete placeholders instead of closing them
+     * @param max_gap maximum number of placeholders for one issue, 0 for no limit
+     * @return the iid and url of the new issue
+     * @note gitlab only honors iid for admins and project owners. Else, placeholders are
+     * created until the next iid is the artifact id
      */
-    pub fn generate_issue(&self, issue: &GitlabIssue) {
+    fn create_issue_at_artifact_id(&self, issue: &GitlabIssue, description: String,
+                                   next_iids: &mut HashMap<String, u64>,
+                                   delete_placeholders: bool, max_gap: u64) -> Option<(u64, String)> {
+        let wanted = issue.tuleap_id;
+        let mut placeholders: Vec<u64> = Vec::new();
+        let mut next_iid = match next_iids.get(&issue.project_url) {
+            Some(next_iid) => *next_iid,
+            None => {
+                let (iid, web_url) = self.create_issue(issue, description.clone(), Some(wanted))?;
+                if iid >= wanted {
+                    if iid > wanted {
+                        warn!("{} created with iid {} instead of {}", issue.title, iid, wanted);
+                        next_iids.insert(issue.project_url.clone(), iid + 1);
+                    }
+                    return Some((iid, web_url));
+                }
+                warn!("iid {} ignored for {} (got {}), fill with placeholders", wanted, issue.title, iid);
+                // The real issue is already there, it becomes the first placeholder
+                self.make_placeholder(&*issue.project_url, iid);
+                placeholders.push(iid);
+                iid + 1
+            }
+        };
+        if max_gap > 0 && wanted > next_iid && wanted - next_iid > max_gap {
+            error!("{} needs {} placeholders to get iid {} in {}, more than max_placeholder_gap ({}), not created",
+                   issue.title, wanted - next_iid, wanted, issue.project_url, max_gap);
+            next_iids.insert(issue.project_url.clone(), next_iid);
+            for placeholder in placeholders {
+                self.remove_placeholder(&*issue.project_url, placeholder, delete_placeholders);
+            }
+            return None;
+        }
+        while next_iid < wanted {
+            match self.create_placeholder(&*issue.project_url) {
+                Some(placeholder) => {
+                    placeholders.push(placeholder);
+                    next_iid = placeholder + 1;
+                },
+                None => break
+            }
+        }
+        let result = self.create_issue(issue, description, None);
+        if let Some((iid, _)) = result {
+            if iid != wanted {
+                warn!("{} created with iid {} instead of {}", issue.title, iid, wanted);
+            }
+            next_iids.insert(issue.project_url.clone(), iid + 1);
+        }
+        for placeholder in placeholders {
+            self.remove_placeholder(&*issue.project_url, placeholder, delete_placeholders);
+        }
+        result
+    }
+
+    /**
+     * Create an empty issue used to consume an iid
+     * @param project where to create the placeholder
+     * @return the iid of the placeholder
+     */
+    fn create_placeholder(&self, project: &str) -> Option<u64> {
         let url = format!("{}/api/v4/projects/{}/issues?private_token={}",
-                          self.gitlab_url, issue.project_url, self.private_token);
+                          self.gitlab_url, project, self.private_token);
+        let mut post = HashMap::new();
+        post.insert("title", "Placeholder");
+        post.insert("description", "Placeholder used to keep tuleap's numbering.");
+        let mut req = self.client.post(&*url).json(&post).send().ok()?;
+        let body = req.text().ok()?;
+        let result: Value = from_str(&*body).ok()?;
+        let iid = result["iid"].as_u64();
+        debug!("Placeholder {:?} created in {}", iid, project);
+        iid
+    }
 
-        let mut description: String = issue.description.clone();
+    /**
+     * Turn an existing issue into a placeholder
+     * @param project of the issue
+     * @param iid of the issue
+     */
+    fn make_placeholder(&self, project: &str, iid: u64) {
+        let url = format!("{}/api/v4/projects/{}/issues/{}?private_token={}",
+                          self.gitlab_url, project, iid, self.private_token);
+        let mut post = HashMap::new();
+        post.insert("title", "Placeholder");
+        post.insert("description", "Placeholder used to keep tuleap's numbering.");
+        post.insert("labels", "");
+        let _ = self.client.put(&*url).json(&post).send();
+    }
 
-        for attachment in issue.attachments.clone() {
-            let url = format!("{}/api/v4/projects/{}/uploads?private_token={}",
-                             self.gitlab_url, issue.project_url, self.private_token);
+    /**
+     * Delete or close a placeholder
+     * @param project of the placeholder
+     * @param iid of the placeholder
+     * @param delete if false, or if the deletion fails, the placeholder is closed
+     */
+    fn remove_placeholder(&self, project: &str, iid: u64, delete: bool) {
+        let url = format!("{}/api/v4/projects/{}/issues/{}?private_token={}",
+                          self.gitlab_url, project, iid, self.private_token);
+        if delete {
+            match self.client.delete(&*url).send() {
+                Ok(ref res) if res.status().is_success() => {
+                    info!("Delete placeholder {}/{}", project, iid);
+                    return;
+                },
+                _ => warn!("Failed to delete placeholder {}/{}, close it", project, iid)
+            }
+        }
+        info!("Close placeholder {}/{}", project, iid);
+        let url = format!("{}&state_event=close", url);
+        let _ = self.client.put(&*url).send();
+    }
 
-            let form = reqwest::multipart::Form::new().file("file", attachment.clone());
-            match form {
-                Err(_) => { error!("ABORT {:?}", issue.title); return; },
-                _ => {}
+    /**
+     * Generate a gitlab issue on a tracker from the API
+     * @param issue to generate
+     * @return the result of the creation
+     */
+    pub fn generate_issue(&self, issue: &GitlabIssue) -> PostResult {
+        let started = Instant::now();
+        let (description, failed) = self.upload_attachments(issue);
+        let uploads = GitlabClient::uploads(&*description);
+        let created = self.create_issue(issue, description, None);
+        self.complete_issue(issue, created, failed, uploads, true, started)
+    }
+
+    /**
+     * Finish a created issue and build the result
+     * @param issue to generate
+     * @param created iid and url of the issue if created
+     * @param failed_attachments the attachments of the description which failed to upload
+     * @param uploads number of files uploaded in the description
+     * @param related if the issues of the related projects are created
+     * @param started when the generation started
+     * @return the result of the creation
+     */
+    fn complete_issue(&self, issue: &GitlabIssue, created: Option<(u64, String)>,
+                      failed_attachments: Vec<String>, uploads: usize, related: bool,
+                      started: Instant) -> PostResult {
+        let mut result = PostResult::default();
+        result.failed_attachments = failed_attachments;
+        if let Some((iid, web_url)) = created {
+            result.uploads = uploads;
+            self.finish_issue(issue, iid, &mut result);
+            if related {
+                result.related_urls = self.generate_related(issue, iid, &*web_url);
+            } else if !issue.related.projects().is_empty() {
+                warn!("{} is only created in {}, related issues are not created when iids are preserved",
+                      issue.title, issue.project_url);
             }
-            let mut req = self.client.post(&*url).multipart(form.unwrap()).send();
-            match req {
-                Err(_) => return,
-                _ => {}
+            result.iid = Some(iid);
+            result.web_url = web_url;
+        }
+        result.post_time = started.elapsed();
+        result
+    }
+
+    /**
+     * Create the issues of the related projects and link them
+     * @param issue the main issue
+     * @param iid of the main issue
+     * @param web_url of the main issue
+     * @return the urls of the created issues
+     */
+    fn generate_related(&self, issue: &GitlabIssue, iid: u64, web_url: &str) -> Vec<String> {
+        let (projects, copies) = match issue.related {
+            RelatedProjects::Links(ref projects) => (projects, false),
+            RelatedProjects::Copies(ref projects) => (projects, true)
+        };
+        let mut linked: Vec<(String, u64)> = vec![(issue.project_url.clone(), iid)];
+        let mut urls: Vec<String> = Vec::new();
+        for project in projects {
+            let mut related = issue.clone();
+            related.project_url = project.clone();
+            related.related = RelatedProjects::Links(Vec::new());
+            if !copies {
+                related.description = format!("Issue generated from Tuleap's migration script.  \nTracked in {}", web_url);
+                related.comments.clear();
+                related.attachments.clear();
             }
-            let body = match req.unwrap().text() {
-                Ok(body) => body,
-                Err(_) => String::from("")
+            let result = self.generate_issue(&related);
+            let related_iid = match result.iid {
+                Some(related_iid) => related_iid,
+                None => {
+                    warn!("Failed to create {} in {}", issue.title, project);
+                    continue;
+                }
             };
-            let result = from_str(&*body);
-            match result {
-                Err(_) => { error!("ABORT {:?}", issue.title); return; },
-                _ => {}
-            }
-            let result: Value = result.unwrap();
-            let md = result["markdown"].as_str().unwrap_or("");
-            info!("Post new file: {}", attachment);
-            debug!("{}", body);
+            // Copies are all linked together, links only to the main issue
+            let targets = if copies { linked.len() } else { 1 };
+            for &(ref target_project, target_iid) in linked.iter().take(targets) {
+                self.link_issues(project, related_iid, target_project, target_iid);
+            }
+            linked.push((project.clone(), related_iid));
+            urls.push(result.web_url);
+        }
+        urls
+    }
+
+    /**
+     * Mark two issues as related
+     * @param project of the first issue
+     * @param iid of the first issue
+     * @param target_project of the second issue
+     * @param target_iid of the second issue
+     */
+    pub fn link_issues(&self, project: &str, iid: u64, target_project: &str, target_iid: u64) {
+        let url = format!("{}/api/v4/projects/{}/issues/{}/links?private_token={}",
+                          self.gitlab_url, project, iid, self.private_token);
+        let mut post = HashMap::new();
+        post.insert("target_project_id", String::from(target_project));
+        post.insert("target_issue_iid", target_iid.to_string());
+        info!("Link {}#{} to {}#{}", project, iid, target_project, target_iid);
+        match self.client.post(&*url).json(&post).send() {
+            Ok(ref res) if res.status().is_success() => {},
+            _ => warn!("Failed to link {}#{} to {}#{}", project, iid, target_project, target_iid)
+        }
+    }
+
+    /**
+     * Update an issue already migrated
+     * @param issue the new version of the issue
+     * @param mapped the gitlab issue and what was already posted on it
+     * @return what is now posted on the gitlab issue
+     */
+    pub fn update_issue(&self, issue: &GitlabIssue, mapped: &MappedIssue) -> MappedIssue {
+        let mut result = mapped.clone();
+        if mapped.project != issue.project_url {
+            warn!("{} moved from {} to {}, keep it in {}", issue.title, mapped.project, issue.project_url, mapped.project);
+        }
+        let url = format!("{}/api/v4/projects/{}/issues/{}?private_token={}",
+                          self.gitlab_url, mapped.project, mapped.iid, self.private_token);
+        // Only push what changed on Tuleap, to keep the changes made on gitlab
+        let mut post = HashMap::new();
+        if issue.title != mapped.title {
+            post.insert("title", issue.title.clone());
+        }
+        if issue.assignee != mapped.assignee {
+            post.insert("assignee_id", issue.assignee.clone());
+        }
+        // Labels added on gitlab are kept
+        let added: Vec<&str> = issue.labels.iter().filter(|l| !mapped.labels.contains(l)).map(|l| &**l).collect();
+        let removed: Vec<&str> = mapped.labels.iter().filter(|l| !issue.labels.contains(l)).map(|l| &**l).collect();
+        if !added.is_empty() {
+            post.insert("add_labels", added.join(","));
+        }
+        if !removed.is_empty() {
+            post.insert("remove_labels", removed.join(","));
+        }
+        if issue.closed != mapped.closed {
+            post.insert("state_event", String::from(if issue.closed { "close" } else { "reopen" }));
+        }
+        let applied = post.is_empty() || {
+            info!("Update issue {}", issue.title);
+            match self.client.put(&*url).json(&post).send() {
+                Ok(ref res) if res.status().is_success() => true,
+                _ => {
+                    warn!("Failed to update {}/{}, the changes are pushed by the next update",
+                          mapped.project, mapped.iid);
+                    false
+                }
+            }
+        };
+        if applied {
+            result.title = issue.title.clone();
+            result.labels = issue.labels.clone();
+            result.assignee = issue.assignee.clone();
+            result.closed = issue.closed;
+            if issue.closed != mapped.closed {
+                // Pushed to gitlab, not to mirror back
+                result.mirrored_closed = issue.closed;
+            }
+        }
+
+        // New comments
+        for comment in issue.new_comments(mapped) {
+            info!("Generate new comment for {}", issue.title);
+            let new_attachments: Vec<&String> = comment.attachments.iter()
+                .filter(|a| !result.attachments.contains(a)).collect();
+            let (body, failed) = self.comment_body(&*mapped.project, issue, comment, &new_attachments);
+            let uploads = GitlabClient::uploads(&*body);
+            if self.post_comment(&*mapped.project, mapped.iid, body, &*comment.created_at) {
+                result.comments += 1;
+                result.last_changeset = Some(comment.changeset_id);
+                result.uploads += uploads;
+                result.attachments.extend(new_attachments.into_iter().filter(|a| !failed.contains(a)).cloned());
+                result.attachments.extend(issue.inline_files.iter().enumerate()
+                                          .filter(|&(i, ref f)| (comment.body.contains(&*inline_file_marker(i, false))
+                                                                 || comment.body.contains(&*inline_file_marker(i, true)))
+                                                  && !failed.contains(&f.0))
+                                          .map(|(_, f)| f.0.clone()));
+            } else {
+                break;
+            }
+        }
+
+        // New attachments
+        for attachment in issue.attachments.iter() {
+            if result.attachments.contains(attachment) {
+                continue;
+            }
+            if let Some(md) = self.upload_file(&*mapped.project, attachment) {
+                let uploads = GitlabClient::uploads(&*md);
+                if self.post_comment(&*mapped.project, mapped.iid, md, "") {
+                    result.attachments.push(attachment.clone());
+                    result.uploads += uploads;
+                }
+            }
+        }
+        result
+    }
+
+    /**
+     * Upload a file on a project
+     * @param project where to upload
+     * @param attachment path of the file
+     * @return the markdown to use the file
+     * @note identical files are uploaded once per project and their markdown reused
+     */
+    fn upload_file(&self, project: &str, attachment: &String) -> Option<String> {
+        let hash = sha256_file(&*attachment)?;
+        let key = (String::from(project), hash.clone());
+        if let Some(md) = self.uploads.lock().unwrap().get(&key) {
+            debug!("Reuse uploaded file: {}", attachment);
+            return Some(md.clone());
+        }
+        let size = metadata(&*attachment).ok()?.len();
+        if self.max_attachment_size > 0 && size > self.max_attachment_size {
+            let md = self.oversized_file(attachment, &*hash, size)?;
+            self.uploads.lock().unwrap().insert(key, md.clone());
+            return Some(md);
+        }
+        let url = format!("{}/api/v4/projects/{}/uploads?private_token={}",
+                         self.gitlab_url, project, self.private_token);
+
+        let form = reqwest::multipart::Form::new().file("file", attachment.clone()).ok()?;
+        let mut req = self.client.post(&*url).multipart(form).send().ok()?;
+        let body = match req.text() {
+            Ok(body) => body,
+            Err(_) => String::from("")
+        };
+        let result: Value = from_str(&*body).ok()?;
+        info!("Post new file: {}", attachment);
+        debug!("{}", body);
+        let md = result["markdown"].as_str().map(String::from)?;
+        self.uploads.lock().unwrap().insert(key, md.clone());
+        Some(md)
+    }
+
+    /**
+     * Replace a file too big to be uploaded by a link to the archive, or a mention
+     * @param attachment path of the file
+     * @param hash sha256 of the file
+     * @param size of the file in bytes
+     * @return the markdown to use instead of the file
+     */
+    fn oversized_file(&self, attachment: &str, hash: &str, size: u64) -> Option<String> {
+        let name = Path::new(attachment).file_name()?.to_string_lossy().into_owned();
+        let size_mb = size as f64 / 1048576.0;
+        if self.archive_dir.is_empty() {
+            warn!("{} is too big ({:.1} MB) and is not migrated", attachment, size_mb);
+            return Some(format!("*{} ({:.1} MB) was too big to be migrated*", name, size_mb));
+        }
+        // Archived by content, so identical files are only copied once
+        let dir = format!("{}/{}", self.archive_dir, hash);
+        let path = format!("{}/{}", dir, name);
+        if metadata(&*path).is_err() {
+            if let Err(e) = create_dir_all(&*dir).and_then(|_| copy(attachment, &*path)) {
+                error!("Failed to archive {}: {}", attachment, e);
+                return None;
+            }
+        }
+        info!("{} is too big ({:.1} MB), archived in {}", attachment, size_mb, path);
+        let encoded: String = form_urlencoded::byte_serialize(name.as_bytes()).collect();
+        Some(format!("[{} ({:.1} MB)]({}/{}/{})", name, size_mb, self.archive_url.trim_end_matches('/'), hash,
+                     encoded.replace("+", "%20")))
+    }
+
+    /**
+     * Upload the attachments of an issue
+     * @param issue to generate
+     * @return the description with the markdown of each attachment, and the attachments which failed
+     * @note an attachment which failed is mentioned in the description instead
+     */
+    fn upload_attachments(&self, issue: &GitlabIssue) -> (String, Vec<String>) {
+        let (mut description, mut failed) = self.replace_inline_files(&*issue.project_url, issue, &*issue.description);
+
+        // Files added with a comment are posted with it
+        for attachment in issue.attachments.iter()
+            .filter(|a| !issue.comments.iter().any(|c| c.attachments.contains(a))) {
             description += "  \n";
-            description += md;
+            match self.upload_file(&*issue.project_url, attachment) {
+                Some(md) => description += &*md,
+                None => {
+                    error!("Failed to upload {} for {:?}", attachment, issue.title);
+                    description += &*GitlabClient::failed_file(attachment);
+                    failed.push(attachment.clone());
+                }
+            }
         }
+        (description, failed)
+    }
+
+    /**
+     * @param text posted on gitlab
+     * @return the number of files uploaded to gitlab used in text
+     */
+    fn uploads(text: &str) -> usize {
+        text.matches("](/uploads/").count()
+    }
+
+    /**
+     * @param attachment path of a file which failed to upload
+     * @return the markdown mentioning the file
+     */
+    fn failed_file(attachment: &str) -> String {
+        let name = Path::new(attachment).file_name().map_or(String::from(attachment), |n| n.to_string_lossy().into_owned());
+        format!("*{} could not be migrated*", name)
+    }
+
+    /**
+     * Upload the attachments of a comment
+     * @param project of the issue
+     * @param issue of the comment
+     * @param comment to post
+     * @param attachments of the comment to upload
+     * @return the body with the markdown of each attachment, and the attachments which failed
+     */
+    fn comment_body(&self, project: &str, issue: &GitlabIssue, comment: &GitlabComment,
+                    attachments: &[&String]) -> (String, Vec<String>) {
+        let (mut body, mut failed) = self.replace_inline_files(project, issue, &*comment.body);
+        for attachment in attachments {
+            body += "  \n";
+            match self.upload_file(project, attachment) {
+                Some(md) => body += &*md,
+                None => {
+                    error!("Failed to upload {} in a comment", attachment);
+                    body += &*GitlabClient::failed_file(attachment);
+                    failed.push((*attachment).clone());
+                }
+            }
+        }
+        (body, failed)
+    }
+
+    /**
+     * Upload the files referenced in a text and replace their markers
+     * @param project where to upload
+     * @param issue with the files
+     * @param text with markers
+     * @return the text with the markdown of each file, or its tuleap url if the upload failed,
+     * and the files which failed
+     */
+    fn replace_inline_files(&self, project: &str, issue: &GitlabIssue, text: &str) -> (String, Vec<String>) {
+        let mut result = String::from(text);
+        let mut failed: Vec<String> = Vec::new();
+        for (i, &(ref path, ref url)) in issue.inline_files.iter().enumerate() {
+            let marker = inline_file_marker(i, false);
+            let url_marker = inline_file_marker(i, true);
+            if !result.contains(&*marker) && !result.contains(&*url_marker) {
+                continue;
+            }
+            let md = match self.upload_file(project, path) {
+                Some(md) => md,
+                None => {
+                    warn!("Failed to upload inline file {}, keep {}", path, url);
+                    failed.push(path.clone());
+                    url.clone()
+                }
+            };
+            // In html attributes, only the url of the file
+            let file_url = GitlabClient::markdown_url(&*md).unwrap_or(url);
+            result = result.replace(&*url_marker, file_url).replace(&*marker, &*md);
+        }
+        (result, failed)
+    }
+
+    /**
+     * @param md markdown of an uploaded or archived file
+     * @return the url of the file, None if md is not a link
+     */
+    fn markdown_url(md: &str) -> Option<&str> {
+        let start = md.rfind("](")? + 2;
+        if md.ends_with(')') && start < md.len() {
+            Some(&md[start..md.len() - 1])
+        } else {
+            None
+        }
+    }
+
+    /**
+     * Create the issue itself
+     * @param issue to generate
+     * @param description the description including attachments
+     * @param iid the iid to ask for, if any
+     * @return the iid and url of the new issue
+     */
+    fn create_issue(&self, issue: &GitlabIssue, description: String, iid: Option<u64>) -> Option<(u64, String)> {
+        let url = format!("{}/api/v4/projects/{}/issues?private_token={}",
+                          self.gitlab_url, issue.project_url, self.private_token);
 
         // Generate first post
         let mut post = HashMap::new();
@@ -135,39 +942,54 @@ impl GitlabClient {
         post.insert("assignee_id", issue.assignee.clone());
         post.insert("created_at", issue.created_at.clone());
         post.insert("labels", issue.labels.clone().join(","));
+        if let Some(iid) = iid {
+            post.insert("iid", iid.to_string());
+        }
         info!("Generate new issue: {}", issue.title);
         debug!("{}", issue);
 
         // Create issue and retrieve iid
-        let req = self.client.post(&*url).json(&post).send();
-        match req {
-            Err(_) => { error!("ABORT {:?}", issue.title); return; },
-            _ => {}
-        }
-        let body = match req.unwrap().text() {
+        let mut req = match self.client.post(&*url).json(&post).send() {
+            Ok(req) => req,
+            Err(e) => { error!("ABORT {:?}: {}", issue.title, e); return None; }
+        };
+        let body = match req.text() {
             Ok(body) => body,
             Err(_) => String::from("")
         };
-        let result = from_str(&*body);
-        match result {
-            Err(_) => { error!("ABORT {:?}", issue.title); return; },
-            _ => {}
+        let result: Value = from_str(&*body).unwrap_or(Value::Null);
+        match result["iid"].as_u64() {
+            Some(iid) if req.status().is_success() => {
+                Some((iid, String::from(result["web_url"].as_str().unwrap_or(""))))
+            },
+            _ => {
+                error!("ABORT {:?}: gitlab answered {} {}", issue.title, req.status(), body);
+                None
+            }
         }
-        let result: Value = result.unwrap();
-        let iid = result["iid"].as_u64().unwrap_or(0);
+    }
 
-        // Post comments
-        let url = format!("{}/api/v4/projects/{}/issues/{}/notes?private_token={}",
-                          self.gitlab_url, issue.project_url, iid, self.private_token);
+    /**
+     * Post comments and close the issue if needed
+     * @param issue to generate
+     * @param iid of the created issue
+     * @param result where uploads, failed comments and attachments are added
+     */
+    fn finish_issue(&self, issue: &GitlabIssue, iid: u64, result: &mut PostResult) {
         for comment in issue.comments.iter() {
-            let mut post = HashMap::new();
-            post.insert("body", comment.body.clone());
-            post.insert("created_at", comment.created_at.clone());
             info!("Generate new comment for {}", issue.title);
             debug!("{}", comment);
-
-            // Create issue and retrieve iid
-            let _ = self.client.post(&*url).json(&post).send();
+            let attachments: Vec<&String> = comment.attachments.iter().collect();
+            let (body, failed_attachments) = self.comment_body(&*issue.project_url, issue, comment, &attachments);
+            // The comment is posted even without some of its files
+            let uploads = GitlabClient::uploads(&*body);
+            if self.post_comment(&*issue.project_url, iid, body, &*comment.created_at) {
+                result.uploads += uploads;
+                result.last_changeset = Some(comment.changeset_id);
+            } else {
+                result.failed_comments += 1;
+            }
+            result.failed_attachments.extend(failed_attachments);
         }
 
         // Lock issue if done
@@ -181,6 +1003,226 @@ impl GitlabClient {
         }
     }
 
+    /**
+     * Post a note on an issue
+     * @param project of the issue
+     * @param iid of the issue
+     * @param body of the note
+     * @param created_at date of the note, now if empty
+     * @return if the note was created
+     */
+    fn post_comment(&self, project: &str, iid: u64, body: String, created_at: &str) -> bool {
+        let url = format!("{}/api/v4/projects/{}/issues/{}/notes?private_token={}",
+                          self.gitlab_url, project, iid, self.private_token);
+        let mut post = HashMap::new();
+        post.insert("body", body);
+        if !created_at.is_empty() {
+            post.insert("created_at", String::from(created_at));
+        }
+        match self.client.post(&*url).json(&post).send() {
+            Ok(res) => res.status().is_success(),
+            Err(_) => false
+        }
+    }
+
+    /**
+     * Find a project or a group by its path
+     * @param kind "projects" or "groups"
+     * @param path like group/sub/project
+     * @return the id
+     */
+    fn get_id_by_path(&self, kind: &str, path: &str) -> Option<u64> {
+        let encoded: String = form_urlencoded::byte_serialize(path.as_bytes()).collect();
+        let url = format!("{}/api/v4/{}/{}?private_token={}",
+                          self.gitlab_url, kind, encoded, self.private_token);
+        let mut req = self.client.get(&*url).send().ok()?;
+        if !req.status().is_success() {
+            return None;
+        }
+        let result: Value = from_str(&*req.text().ok()?).ok()?;
+        result["id"].as_u64()
+    }
+
+    /**
+     * @param path of the project, like group/sub/project
+     * @return the id of the project if it exists
+     */
+    pub fn get_project_id(&self, path: &str) -> Option<u64> {
+        self.get_id_by_path("projects", path)
+    }
+
+    /**
+     * Create a project with issues enabled
+     * @param path of the project, like group/sub/project. The group must exist
+     * @param name of the project
+     * @param visibility private, internal or public
+     * @return the id of the new project
+     */
+    pub fn create_project(&self, path: &str, name: &str, visibility: &str) -> Option<u64> {
+        let (namespace, project_path) = match path.rfind('/') {
+            Some(idx) => (&path[..idx], &path[idx + 1..]),
+            None => ("", path)
+        };
+        let mut post = json!({
+            "name": name,
+            "path": project_path,
+            "visibility": visibility,
+            "issues_enabled": true
+        });
+        if !namespace.is_empty() {
+            match self.get_id_by_path("groups", namespace) {
+                Some(id) => post["namespace_id"] = json!(id),
+                None => {
+                    error!("Group {} not found, cannot create {}", namespace, path);
+                    return None;
+                }
+            }
+        }
+        let url = format!("{}/api/v4/projects?private_token={}", self.gitlab_url, self.private_token);
+        info!("Create project {}", path);
+        let mut req = self.client.post(&*url).json(&post).send().ok()?;
+        let body = req.text().ok()?;
+        let result: Value = from_str(&*body).ok()?;
+        if result["id"].is_null() {
+            error!("Failed to create project {}: {}", path, body);
+        }
+        result["id"].as_u64()
+    }
+
+    /**
+     * Enable issues on a project and set its visibility
+     * @param id of the project
+     * @param visibility private, internal or public, None to keep it
+     */
+    pub fn update_project(&self, id: u64, visibility: Option<&str>) {
+        let url = format!("{}/api/v4/projects/{}?private_token={}", self.gitlab_url, id, self.private_token);
+        let mut put = json!({
+            "issues_enabled": true
+        });
+        if let Some(visibility) = visibility {
+            put["visibility"] = json!(visibility);
+        }
+        info!("Update project {}", id);
+        let _ = self.client.put(&*url).json(&put).send();
+    }
+
+    /**
+     * @return the id of the user owning the token
+     */
+    pub fn current_user_id(&self) -> Option<u64> {
+        let url = format!("{}/api/v4/user?private_token={}", self.gitlab_url, self.private_token);
+        let mut req = self.client.get(&*url).send().ok()?;
+        let user: Value = from_str(&*req.text().ok()?).ok()?;
+        user["id"].as_u64()
+    }
+
+    /**
+     * Retrieve an issue
+     * @param project of the issue
+     * @param iid of the issue
+     * @return a Json value from the API
+     */
+    pub fn get_issue(&self, project: &str, iid: u64) -> Option<Value> {
+        let url = format!("{}/api/v4/projects/{}/issues/{}?private_token={}",
+                          self.gitlab_url, project, iid, self.private_token);
+        let mut req = self.client.get(&*url).send().ok()?;
+        if !req.status().is_success() {
+            return None;
+        }
+        from_str(&*req.text().ok()?).ok()
+    }
+
+    /**
+     * Retrieve all notes of an issue, oldest first
+     * @param project of the issue
+     * @param iid of the issue
+     * @return a vec of Json values from the API
+     */
+    pub fn get_notes(&self, project: &str, iid: u64) -> Vec<Value> {
+        let mut all_notes: Vec<Value> = Vec::new();
+        let mut page = 1;
+        loop {
+            let url = format!("{}/api/v4/projects/{}/issues/{}/notes?private_token={}&sort=asc&order_by=created_at&per_page=100&page={}",
+                              self.gitlab_url, project, iid, self.private_token, page);
+            let body = match self.client.get(&*url).send() {
+                Ok(mut req) => req.text().unwrap_or(String::new()),
+                Err(_) => break
+            };
+            let mut notes: Vec<Value> = from_str(&*body).unwrap_or(Vec::new());
+            if notes.is_empty() {
+                break;
+            }
+            all_notes.append(&mut notes);
+            page += 1;
+        }
+        all_notes
+    }
+
+    /**
+     * Compare a migrated issue with what gitlab holds
+     * @param mapped the expected issue
+     * @param bot id of the migration account, author of migrated notes
+     * @return a description of each discrepancy, empty if the issue is as expected
+     */
+    pub fn verify_issue(&self, mapped: &MappedIssue, bot: u64) -> Vec<String> {
+        let mut discrepancies: Vec<String> = Vec::new();
+        let issue = match self.get_issue(&*mapped.project, mapped.iid) {
+            Some(issue) => issue,
+            None => {
+                discrepancies.push(String::from("issue not found"));
+                return discrepancies;
+            }
+        };
+        let title = issue["title"].as_str().unwrap_or("");
+        if title != mapped.title {
+            discrepancies.push(format!("title is \"{}\" instead of \"{}\"", title, mapped.title));
+        }
+        let mut labels: Vec<String> = match issue["labels"].as_array() {
+            Some(l) => l.iter().map(|l| String::from(l.as_str().unwrap_or(""))).collect(),
+            None => Vec::new()
+        };
+        let mut expected_labels = mapped.labels.clone();
+        labels.sort();
+        expected_labels.sort();
+        expected_labels.dedup();
+        if labels != expected_labels {
+            discrepancies.push(format!("labels are {:?} instead of {:?}", labels, expected_labels));
+        }
+        let assignee = match issue["assignee"]["id"].as_u64() {
+            Some(id) => id.to_string(),
+            None => String::new()
+        };
+        if assignee != mapped.assignee {
+            discrepancies.push(format!("assignee is \"{}\" instead of \"{}\"", assignee, mapped.assignee));
+        }
+        let closed = issue["state"].as_str() == Some("closed");
+        if closed != mapped.closed {
+            discrepancies.push(format!("state is {} instead of {}", issue["state"],
+                                       if mapped.closed { "closed" } else { "opened" }));
+        }
+
+        // Migrated notes are either comments or new attachments
+        let mut comments = 0;
+        let mut uploads = GitlabClient::uploads(issue["description"].as_str().unwrap_or(""));
+        for note in self.get_notes(&*mapped.project, mapped.iid) {
+            if note["system"].as_bool().unwrap_or(false) || note["author"]["id"].as_u64() != Some(bot) {
+                continue;
+            }
+            let body = note["body"].as_str().unwrap_or("");
+            uploads += GitlabClient::uploads(body);
+            if body.starts_with("**Submitted by ") {
+                comments += 1;
+            }
+        }
+        if comments != mapped.comments {
+            discrepancies.push(format!("{} comments instead of {}", comments, mapped.comments));
+        }
+        if uploads != mapped.uploads {
+            discrepancies.push(format!("{} uploaded files instead of {}", uploads, mapped.uploads));
+        }
+        discrepancies
+    }
+
     /**
      * Remove all issues from the gitlab
      * @note run this by doing cargo run clean
@@ -209,3 +1251,22 @@ impl GitlabClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{GitlabClient, inline_file_marker};
+
+    #[test]
+    fn markdown_url() {
+        assert_eq!(GitlabClient::markdown_url("![shot.png](/uploads/abc/shot.png)"), Some("/uploads/abc/shot.png"));
+        assert_eq!(GitlabClient::markdown_url("[big.iso (200.0 MB)](https://files/abc/big.iso)"), Some("https://files/abc/big.iso"));
+        assert_eq!(GitlabClient::markdown_url("*big.iso (200.0 MB) was too big to be migrated*"), None);
+    }
+
+    #[test]
+    fn inline_file_markers_differ() {
+        assert!(!inline_file_marker(1, true).contains(&*inline_file_marker(1, false)));
+        assert!(!inline_file_marker(10, false).contains(&*inline_file_marker(1, false)));
+    }
+}
+
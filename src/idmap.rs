@@ -0,0 +1,187 @@
+use serde_json::{Value, from_str, to_string_pretty};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+/**
+ * Represent an artifact already migrated to gitlab
+ */
+#[derive(Clone)]
+pub struct MappedIssue {
+    pub project: String,
+    pub iid: u64,
+    pub web_url: String,
+    // Expected title, labels and assignee
+    pub title: String,
+    pub labels: Vec<String>,
+    pub assignee: String,
+    // Number of tuleap comments already posted
+    pub comments: usize,
+    // Last tuleap changeset posted as a comment
+    pub last_changeset: Option<u64>,
+    // Attachments already posted
+    pub attachments: Vec<String>,
+    // Files uploaded to gitlab, attachments can also be archive links or mentions
+    pub uploads: usize,
+    // Last known tuleap state
+    pub closed: bool,
+    // Last gitlab state mirrored to tuleap (or pushed from tuleap)
+    pub mirrored_closed: bool,
+    // Last gitlab note mirrored to tuleap
+    pub last_note: u64
+}
+
+/**
+ * Map tuleap artifacts to gitlab issues, saved between runs
+ */
+pub struct IdMap {
+    path: String,
+    pub last_run: Option<String>,
+    entries: HashMap<u64, MappedIssue>,
+    // Project ids resolved from their path
+    projects: HashMap<String, String>
+}
+
+impl IdMap {
+    /**
+     * Load the map from a file
+     * @param path of the file
+     * @return the map, empty if the file doesn't exist
+     */
+    pub fn load(path: String) -> IdMap {
+        let mut map = IdMap {
+            path: path,
+            last_run: None,
+            entries: HashMap::new(),
+            projects: HashMap::new()
+        };
+        let mut content = String::new();
+        match File::open(&*map.path) {
+            Ok(mut file) => { let _ = file.read_to_string(&mut content); },
+            Err(_) => return map
+        }
+        let json: Value = from_str(&*content).ok().expect("Incorrect id map");
+        map.last_run = json["last_run"].as_str().map(String::from);
+        if let Some(projects) = json["projects"].as_object() {
+            for (path, id) in projects {
+                map.projects.insert(path.clone(), String::from(id.as_str().unwrap_or("")));
+            }
+        }
+        if let Some(issues) = json["issues"].as_array() {
+            for i in issues {
+                map.entries.insert(i["tuleap_id"].as_u64().unwrap_or(0), MappedIssue {
+                    project: String::from(i["project"].as_str().unwrap_or("")),
+                    iid: i["iid"].as_u64().unwrap_or(0),
+                    web_url: String::from(i["web_url"].as_str().unwrap_or("")),
+                    title: String::from(i["title"].as_str().unwrap_or("")),
+                    labels: IdMap::strings(&i["labels"]),
+                    assignee: String::from(i["assignee"].as_str().unwrap_or("")),
+                    comments: i["comments"].as_u64().unwrap_or(0) as usize,
+                    last_changeset: i["last_changeset"].as_u64(),
+                    attachments: IdMap::strings(&i["attachments"]),
+                    // Maps written before uploads were counted
+                    uploads: i["uploads"].as_u64().map_or(IdMap::strings(&i["attachments"]).len(), |u| u as usize),
+                    closed: i["closed"].as_bool().unwrap_or(false),
+                    // Maps written before gitlab states were kept apart
+                    mirrored_closed: i["mirrored_closed"].as_bool().or(i["closed"].as_bool()).unwrap_or(false),
+                    last_note: i["last_note"].as_u64().unwrap_or(0)
+                });
+            }
+        }
+        map
+    }
+
+    /**
+     * @param array a Json array of strings
+     * @return the strings, empty if not an array
+     */
+    fn strings(array: &Value) -> Vec<String> {
+        match array.as_array() {
+            Some(a) => a.iter().map(|a| String::from(a.as_str().unwrap_or(""))).collect(),
+            None => Vec::new()
+        }
+    }
+
+    /**
+     * Write the map to its file
+     */
+    pub fn save(&self) {
+        let issues: Vec<Value> = self.ids().iter().map(|id| {
+            let m = &self.entries[id];
+            json!({
+                "tuleap_id": id,
+                "project": m.project,
+                "iid": m.iid,
+                "web_url": m.web_url,
+                "title": m.title,
+                "labels": m.labels,
+                "assignee": m.assignee,
+                "comments": m.comments,
+                "last_changeset": m.last_changeset,
+                "attachments": m.attachments,
+                "uploads": m.uploads,
+                "closed": m.closed,
+                "mirrored_closed": m.mirrored_closed,
+                "last_note": m.last_note
+            })
+        }).collect();
+        let json = json!({
+            "last_run": self.last_run,
+            "projects": self.projects,
+            "issues": issues
+        });
+        let mut file = File::create(&*self.path).ok().expect("Failed to create id map");
+        file.write_all(to_string_pretty(&json).unwrap().as_bytes()).ok().expect("Failed to write id map");
+    }
+
+    /**
+     * @param tuleap_id the artifact id
+     * @return the gitlab issue for this artifact if already migrated
+     */
+    pub fn get(&self, tuleap_id: u64) -> Option<&MappedIssue> {
+        self.entries.get(&tuleap_id)
+    }
+
+    /**
+     * @return ids of all migrated artifacts
+     */
+    pub fn ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.entries.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /**
+     * Store (or replace) the gitlab issue of an artifact
+     * @param tuleap_id the artifact id
+     * @param issue the gitlab issue
+     */
+    pub fn insert(&mut self, tuleap_id: u64, issue: MappedIssue) {
+        self.entries.insert(tuleap_id, issue);
+    }
+
+    /**
+     * @param path of a gitlab project
+     * @return its id if already resolved
+     */
+    pub fn project(&self, path: &str) -> Option<String> {
+        self.projects.get(path).cloned()
+    }
+
+    /**
+     * Store the id of a gitlab project
+     * @param path of the project
+     * @param id of the project
+     */
+    pub fn set_project(&mut self, path: String, id: String) {
+        self.projects.insert(path, id);
+    }
+
+    /**
+     * Forget every artifact
+     */
+    pub fn clear(&mut self) {
+        self.last_run = None;
+        self.entries.clear();
+    }
+}
@@ -1,30 +1,376 @@
 use chrono::prelude::*;
-use gitlabclient::{GitlabIssue, GitlabComment};
+use gitlabclient::{GitlabIssue, GitlabComment, RelatedProjects, inline_file_marker};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::remove_dir_all;
-use tuleapclient::TuleapClient;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Instant;
+use tuleapclient::{MIRROR_MARKER, TuleapClient};
+
+// Fields filled by tuleap (id, submitter, dates, rank and references), never reported as skipped
+const SYSTEM_FIELD_TYPES: [&'static str; 8] = ["aid", "atid", "subby", "subon", "lud", "luby", "priority", "cross"];
+
+/**
+ * A tracker field or a value of a list field, by id or by label
+ */
+#[derive(Clone, PartialEq)]
+pub enum TuleapRef {
+    // Field id or bind value id, which survive renames and translations
+    Id(u64),
+    Label(String)
+}
+// Used for println!
+impl fmt::Display for TuleapRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TuleapRef::Id(id) => write!(f, "#{}", id),
+            TuleapRef::Label(ref label) => write!(f, "{}", label)
+        }
+    }
+}
+
+impl TuleapRef {
+    /**
+     * @param config an id (number) or a label (string)
+     * @return the reference, None if config is neither
+     */
+    pub fn from_config(config: &Value) -> Option<TuleapRef> {
+        match *config {
+            Value::Number(ref n) => n.as_u64().map(TuleapRef::Id),
+            Value::String(ref s) => Some(TuleapRef::Label(s.clone())),
+            _ => None
+        }
+    }
+
+    /**
+     * @param field the Json value of a field, from an artifact or a tracker
+     * @return if this is the field
+     */
+    pub fn matches_field(&self, field: &Value) -> bool {
+        match *self {
+            TuleapRef::Id(id) => field["field_id"].as_u64() == Some(id),
+            TuleapRef::Label(ref label) => field["label"] == **label
+        }
+    }
+
+    /**
+     * @param field the Json value of a field of an artifact
+     * @return if the field contains this value
+     */
+    pub fn in_field(&self, field: &Value) -> bool {
+        match *self {
+            TuleapRef::Id(id) => {
+                field["values"].as_array().map_or(false, |v| v.iter().any(|v| v["id"].as_u64() == Some(id)))
+                    || field["bind_value_ids"].as_array().map_or(false, |v| v.iter().any(|v| v.as_u64() == Some(id)))
+            },
+            TuleapRef::Label(ref label) => IssueRetriever::field_values(field).contains(label)
+        }
+    }
+
+    /**
+     * Use the id of a field given by its label
+     * @param tracker the structure of the tracker
+     * @return the field by id, or unchanged (with a warning) if the tracker doesn't have it
+     */
+    pub fn resolve_field(&self, tracker: &Value) -> TuleapRef {
+        let field = tracker["fields"].as_array().and_then(|f| f.iter().find(|f| self.matches_field(f)));
+        match field.and_then(|f| f["field_id"].as_u64()) {
+            Some(id) => TuleapRef::Id(id),
+            None => {
+                warn!("Field {} not found in tracker {}", self, tracker["id"]);
+                self.clone()
+            }
+        }
+    }
+
+    /**
+     * Use the id of a value given by its label
+     * @param tracker the structure of the tracker
+     * @param field holding the value
+     * @return the value by id, or unchanged if the field has no such value (like a text field)
+     */
+    pub fn resolve_value(&self, tracker: &Value, field: &TuleapRef) -> TuleapRef {
+        let label = match *self {
+            TuleapRef::Label(ref label) => label,
+            TuleapRef::Id(_) => return self.clone()
+        };
+        let values = match TuleapRef::list_values(tracker, field) {
+            Some(values) => values,
+            None => return self.clone()
+        };
+        match values.iter().find(|v| v["label"] == **label).and_then(|v| v["id"].as_u64()) {
+            Some(id) => TuleapRef::Id(id),
+            None => {
+                warn!("Value {} not found in field {} of tracker {}", self, field, tracker["id"]);
+                self.clone()
+            }
+        }
+    }
+
+    /**
+     * @param tracker the structure of the tracker
+     * @param field a list field
+     * @return the values the field can take, None if it is not a list field of the tracker
+     */
+    fn list_values<'a>(tracker: &'a Value, field: &TuleapRef) -> Option<&'a Vec<Value>> {
+        tracker["fields"].as_array()
+            .and_then(|f| f.iter().find(|f| field.matches_field(f)))
+            .and_then(|f| f["values"].as_array())
+    }
+}
+
+/**
+ * What a value of the severity or status field gives on gitlab
+ */
+#[derive(Clone)]
+pub struct ValueMapping {
+    pub value: TuleapRef,
+    // Label added to the issue, if any
+    pub label: Option<String>,
+    pub closed: bool
+}
+
+impl ValueMapping {
+    /**
+     * @param value by label
+     * @param label added to the issue
+     * @param closed if the issue is closed
+     * @return the mapping
+     */
+    fn new(value: &str, label: Option<String>, closed: bool) -> ValueMapping {
+        ValueMapping {
+            value: TuleapRef::Label(String::from(value)),
+            label: label,
+            closed: closed
+        }
+    }
+
+    /**
+     * Severities are recognized by their first character, so translated labels work too
+     * @param scoped_labels use severity:: labels
+     * @param tracker the structure of the tracker, Null for the labels of the tuleap bug tracker
+     * @param field the severity field
+     * @return the mapping of the severities starting with 1, 5 and 9
+     */
+    pub fn default_severities(scoped_labels: bool, tracker: &Value, field: &TuleapRef) -> Vec<ValueMapping> {
+        let label = |scoped: &str, plain: &str| Some(if scoped_labels {
+            format!("severity::{}", scoped)
+        } else {
+            String::from(plain)
+        });
+        let severities = [('1', "1 - Ordinary", label("ordinary", "S - Ordinary")),
+                          ('5', "5 - Major", label("major", "S - Major")),
+                          ('9', "9 - Critical", label("critical", "S - Critical"))];
+        let values = match TuleapRef::list_values(tracker, field) {
+            Some(values) => values,
+            None => return severities.iter().map(|&(_, value, ref label)| ValueMapping::new(value, label.clone(), false)).collect()
+        };
+        let mut mappings = Vec::new();
+        for v in values {
+            let first = v["label"].as_str().and_then(|l| l.chars().next());
+            let severity = severities.iter().find(|s| Some(s.0) == first);
+            if let (Some(&(_, _, ref label)), Some(id)) = (severity, v["id"].as_u64()) {
+                mappings.push(ValueMapping { value: TuleapRef::Id(id), label: label.clone(), closed: false });
+            }
+        }
+        if mappings.is_empty() {
+            warn!("No severity starting with 1, 5 or 9 in field {} of tracker {}, set fields.severity_values",
+                  field, tracker["id"]);
+        }
+        mappings
+    }
+
+    /**
+     * @param scoped_labels use status:: labels
+     * @return the mapping of the statuses of the tuleap bug tracker
+     */
+    pub fn default_statuses(scoped_labels: bool) -> Vec<ValueMapping> {
+        let declined = if scoped_labels { "status::declined" } else { "invalid" };
+        vec![
+            ValueMapping::new("Done", None, true),
+            ValueMapping::new("Declined", Some(String::from(declined)), true)
+        ]
+    }
+}
+
+/**
+ * A reference to a tuleap attachment in a text
+ */
+struct InlineReference {
+    // Part of the text replaced by the file
+    start: usize,
+    end: usize,
+    // Url of the attachment, relative to tuleap
+    path: String,
+    file_id: Option<u64>,
+    name: String,
+    // In an html attribute, where only the url of the file fits
+    url_only: bool
+}
+
+impl InlineReference {
+    /**
+     * @param text from tuleap
+     * @return the first reference to an attachment in text, None if there is none
+     */
+    fn find(text: &str) -> Option<InlineReference> {
+        let is_end = |c: char| c.is_whitespace() || "\\\"'()[]<>".contains(c);
+        let found = text.find("/plugins/tracker/attachments/")?;
+        // The reference may be absolute
+        let mut start = text[..found].rfind(is_end).map_or(0, |i| i + 1);
+        let mut end = text[found..].find(is_end).map_or(text.len(), |i| found + i);
+        // Punctuation ending a sentence
+        end = found + text[found..end].trim_end_matches(|c| c == '.' || c == ',').len();
+        let path = &text[found..end];
+        // The file name follows its id
+        let segment = path.rsplit('/').next().unwrap_or("").split('?').next().unwrap_or("");
+        let name = segment.splitn(2, '-').nth(1).unwrap_or(segment);
+        let file_id = segment.split('-').next().and_then(|i| i.parse().ok());
+        let before = &text[..start];
+        let mut url_only = false;
+        if before.ends_with("](") && text[end..].starts_with(')') {
+            // A markdown image or link is replaced entirely
+            if let Some(open) = before.rfind('[') {
+                start = if before[..open].ends_with('!') { open - 1 } else { open };
+                end += 1;
+            }
+        } else {
+            // Quotes may be escaped in the raw text
+            let attribute = before.trim_end_matches(|c| c == '"' || c == '\'' || c == '\\');
+            let tail = attribute.char_indices().rev().nth(4).map_or(attribute, |(i, _)| &attribute[i..]).to_lowercase();
+            url_only = tail.ends_with("src=") || tail.ends_with("href=");
+        }
+        Some(InlineReference {
+            start: start,
+            end: end,
+            path: String::from(path),
+            file_id: file_id,
+            name: String::from(name),
+            url_only: url_only
+        })
+    }
+}
+
+/**
+ * Fields of a tracker read by the conversion
+ */
+#[derive(Clone)]
+pub struct FieldMap {
+    // Read from the artifact listing, only to leave it out of skipped fields
+    pub title: TuleapRef,
+    pub platform: TuleapRef,
+    pub severity: TuleapRef,
+    pub status: TuleapRef,
+    pub description: TuleapRef,
+    pub attachments: TuleapRef,
+    pub assignee: TuleapRef,
+    pub severity_values: Vec<ValueMapping>,
+    pub status_values: Vec<ValueMapping>
+}
+
+impl Default for FieldMap {
+    fn default() -> FieldMap {
+        FieldMap {
+            title: TuleapRef::Label(String::from("Summary")),
+            platform: TuleapRef::Label(String::from("Platform")),
+            severity: TuleapRef::Label(String::from("Severity")),
+            status: TuleapRef::Label(String::from("Status")),
+            description: TuleapRef::Label(String::from("Original Submission")),
+            attachments: TuleapRef::Label(String::from("Attachments")),
+            assignee: TuleapRef::Label(String::from("Assigned to")),
+            severity_values: ValueMapping::default_severities(false, &Value::Null, &TuleapRef::Label(String::from("Severity"))),
+            status_values: ValueMapping::default_statuses(false)
+        }
+    }
+}
+
+/**
+ * Send artifacts with a given value in a field to a project
+ */
+pub struct RoutingRule {
+    pub field: TuleapRef,
+    pub value: TuleapRef,
+    pub project: String
+}
+
+/**
+ * What to do with artifacts whose platform has no gitlab project
+ */
+pub enum UnmappedPolicy {
+    // Post them in this project
+    Default(String),
+    // Post them in a triage project, with a label and their original platform
+    Triage { project: String, label: String },
+    // Do not migrate them (they are listed in the report)
+    Skip
+}
+
+/**
+ * What to do with artifacts listing several platforms
+ */
+#[derive(Clone, Copy)]
+pub enum MultiPlatformPolicy {
+    // One issue in the first project, with related issues linking to it in the others
+    Links,
+    // One full issue per project, all linked together
+    Copies
+}
 
 /**
  * Used to make the transition between tuleap and gitlab
  */
 pub struct IssueRetriever {
-    all_artifacts: Vec<Value>,
     assignees_map: HashMap<String, String>,
-    project_map: HashMap<String, String>,
-    file_dir: String
+    // Platforms (values of the platform field) and their project
+    project_map: Vec<(TuleapRef, String)>,
+    fields: FieldMap,
+    routing_rules: Vec<RoutingRule>,
+    unmapped_policy: UnmappedPolicy,
+    multi_platform_policy: MultiPlatformPolicy,
+    scoped_labels: bool,
+    // Added to every issue
+    extra_labels: Vec<String>,
+    file_dir: String,
+    workers: usize
 }
 
 impl IssueRetriever {
-    pub fn new(all_artifacts: Vec<Value>,
-               assignees_map: HashMap<String, String>,
-               project_map: HashMap<String, String>,
-               file_dir: String) -> IssueRetriever {
+    /**
+     * @param assignees_map tuleap username to gitlab user id
+     * @param project_map tuleap platform to gitlab project
+     * @param fields read by the conversion
+     * @param routing_rules checked before project_map, the first matching rule wins
+     * @param unmapped_policy for artifacts without project
+     * @param multi_platform_policy for artifacts with several projects
+     * @param scoped_labels use severity:: and status:: labels for severity and status
+     * @param extra_labels added to every issue, like type::task
+     * @param file_dir where attachments are downloaded
+     * @param workers number of artifacts retrieved in parallel
+     */
+    pub fn new(assignees_map: HashMap<String, String>,
+               project_map: Vec<(TuleapRef, String)>,
+               fields: FieldMap,
+               routing_rules: Vec<RoutingRule>,
+               unmapped_policy: UnmappedPolicy,
+               multi_platform_policy: MultiPlatformPolicy,
+               scoped_labels: bool,
+               extra_labels: Vec<String>,
+               file_dir: String,
+               workers: usize) -> IssueRetriever {
         IssueRetriever {
-            all_artifacts: all_artifacts,
             assignees_map: assignees_map,
             project_map: project_map,
-            file_dir: file_dir
+            fields: fields,
+            routing_rules: routing_rules,
+            unmapped_policy: unmapped_policy,
+            multi_platform_policy: multi_platform_policy,
+            scoped_labels: scoped_labels,
+            extra_labels: extra_labels,
+            file_dir: file_dir,
+            workers: workers
         }
     }
 
@@ -53,6 +399,80 @@ impl IssueRetriever {
         result
     }
 
+    /**
+     * @param scope of the label, like severity
+     * @param scoped name of the label in this scope, like major
+     * @param plain name of the label when scoped labels are not used
+     * @return the label to put on the issue
+     */
+    fn mapped_label(&self, scope: &str, scoped: &str, plain: &str) -> String {
+        if self.scoped_labels {
+            format!("{}::{}", scope, scoped)
+        } else {
+            String::from(plain)
+        }
+    }
+
+    /**
+     * Add a label, replacing the label of the same scope if any (like gitlab does)
+     * @param labels of the issue
+     * @param label to add
+     */
+    fn add_label(labels: &mut Vec<String>, label: String) {
+        if let Some(end) = label.rfind("::") {
+            let scope = &label[..end + 2];
+            labels.retain(|l| !(l.starts_with(scope) && !l[scope.len()..].contains("::")));
+        }
+        if !labels.contains(&label) {
+            labels.push(label);
+        }
+    }
+
+    /**
+     * Download the tuleap attachments referenced in a text and replace the references by markers
+     * @param tuleap the tuleap client
+     * @param text the raw text from tuleap
+     * @param id of the artifact
+     * @param inline_files files already referenced in the artifact, new ones are added
+     * @return the text with a marker instead of each reference
+     */
+    fn inline_files(&self, tuleap: &mut TuleapClient, text: String, id: &str,
+                    inline_files: &mut Vec<(String, String)>) -> String {
+        let mut result = String::new();
+        let mut rest = &*text;
+        while let Some(reference) = InlineReference::find(rest) {
+            let text = &rest[reference.start..reference.end];
+            if reference.name.is_empty() {
+                result += &rest[..reference.end];
+                rest = &rest[reference.end..];
+                continue;
+            }
+            let file = match tuleap.get_file(reference.path.clone(), reference.file_id, None, reference.name.clone(),
+                                             self.file_dir.clone(), String::from(id)) {
+                Some(file) => file,
+                None => {
+                    warn!("Failed to get inline file {} in artifact {}", text, id);
+                    result += &rest[..reference.end];
+                    rest = &rest[reference.end..];
+                    continue;
+                }
+            };
+            let index = match inline_files.iter().position(|f| f.0 == file) {
+                Some(index) => index,
+                None => {
+                    inline_files.push((file, format!("{}{}", tuleap.tracker_url(), reference.path)));
+                    inline_files.len() - 1
+                }
+            };
+            debug!("inline file {} in artifact {}", text, id);
+            result += &rest[..reference.start];
+            result += &*inline_file_marker(index, reference.url_only);
+            rest = &rest[reference.end..];
+        }
+        result += rest;
+        result
+    }
+
     /**
      * Remove the first and last character if possible
      * @param string the String to clean
@@ -66,133 +486,442 @@ impl IssueRetriever {
     }
 
     /**
-     * Prepare the migration via the tuleap client
-     * @param tuleap the tuleap client
-     * @return a vec of GitlabIssue for a GitlabClient
+     * Get the values of a field as strings
+     * @param field the Json value of the field
+     * @return the value, or the labels of selected values
      */
-    pub fn tuleap_to_gitlab(&self, mut tuleap: TuleapClient) -> Vec<GitlabIssue> {
-        let _ = remove_dir_all(self.file_dir.clone());
-        let mut gitlab_issues: Vec<GitlabIssue> = Vec::new();
-        let release_date = DateTime::parse_from_rfc3339("2017-07-21T00:00:00+02:00").unwrap();
+    pub fn field_values(field: &Value) -> Vec<String> {
+        let mut result: Vec<String> = Vec::new();
+        match field["value"] {
+            Value::String(ref s) => result.push(s.clone()),
+            Value::Number(ref n) => result.push(n.to_string()),
+            _ => {}
+        }
+        if let Some(values) = field["values"].as_array() {
+            for v in values {
+                if let Some(label) = v["label"].as_str() {
+                    result.push(String::from(label));
+                }
+            }
+        }
+        result
+    }
 
-        // TODO improve with threads
-        for issue in &self.all_artifacts {
-            // Retrieve base issue
-            let details = tuleap.get_artifact_details(issue["id"].to_string());
-            let title = self.clean_txt(self.rm_first_and_last(issue["title"].to_string()));
-            let created_at = self.clean_txt(self.rm_first_and_last(issue["submitted_on"].to_string()));
-            let mut project_url: String = String::from(""); // for now store platform
-            let mut labels: Vec<String> = Vec::new();
-            let mut attachments: Vec<String> = Vec::new();
-            let mut assignee: String = String::from("");
-            let mut description = String::from("Issue generated from Tuleap's migration script.\n**Originally submitted by: ");
-            let mut sender: String;
-            let mut closed = false;
-            if details["submitted_by_details"].is_object() {
-                sender = details["submitted_by_details"]["display_name"].to_string();
-            } else {
-                sender = details["submitted_by_user"]["display_name"].to_string();
+    /**
+     * Check if a field of an artifact is filled
+     * @param field the Json value of the field
+     * @return true if the field has a value
+     */
+    fn has_value(&self, field: &Value) -> bool {
+        let not_empty = |v: &Value| match *v {
+            Value::Null => false,
+            Value::String(ref s) => !s.is_empty(),
+            Value::Array(ref a) => !a.is_empty(),
+            _ => true
+        };
+        not_empty(&field["value"]) || not_empty(&field["values"]) || not_empty(&field["file_descriptions"])
+    }
+
+    /**
+     * Prepare the migration of a batch of artifacts via the tuleap client
+     * @param tuleap the tuleap client, cloned for each worker
+     * @param artifacts the batch to migrate
+     * @return a vec of GitlabIssue for a GitlabClient, in the same order as the artifacts
+     */
+    pub fn tuleap_to_gitlab(&self, tuleap: &TuleapClient, artifacts: &[Value]) -> Vec<GitlabIssue> {
+        let total = artifacts.len();
+        let next = AtomicUsize::new(0);
+        let mut gitlab_issues: Vec<Option<GitlabIssue>> = (0..total).map(|_| None).collect();
+        let (tx, rx) = channel();
+
+        thread::scope(|s| {
+            for _ in 0..self.workers.max(1) {
+                let tx = tx.clone();
+                let next = &next;
+                let mut tuleap = tuleap.clone();
+                s.spawn(move || {
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::SeqCst);
+                        if idx >= total {
+                            break;
+                        }
+                        let issue = self.convert_artifact(&mut tuleap, &artifacts[idx]);
+                        if issue.is_none() {
+                            error!("Failed to retrieve artifact {}, it is not migrated", artifacts[idx]["id"]);
+                        }
+                        if tx.send((idx, issue)).is_err() {
+                            break;
+                        }
+                    }
+                });
             }
-            description += &sender[1..(sender.len()-1)];
-            description += "**";
-            let values = &details["values"];
-            if values.is_array() {
-                for v in values.as_array().unwrap() {
-                    let label = &v["label"];
-                    if label == "Platform" {
-                        project_url = v["values"][0]["label"].to_string();
-                        project_url = match self.project_map.get(&self.rm_first_and_last(project_url)) {
-                            Some(p) => p.clone(),
-                            None => String::from("")
-                        };
-                        project_url = project_url;
-                    } else if label == "Severity" {
-                        let severity =  v["values"][0]["label"].to_string();
-                        match self.rm_first_and_last(severity).chars().next() {
-                            Some('1') => labels.push(String::from("S - Ordinary")),
-                            Some('5') => labels.push(String::from("S - Major")),
-                            Some('9') => labels.push(String::from("S - Critical")),
-                            _ => continue
+            drop(tx);
+            // Workers finish in any order, so store each issue at its artifact's position
+            let mut remaining = total;
+            for (idx, issue) in rx {
+                gitlab_issues[idx] = issue;
+                remaining -= 1;
+                info!("{} artifacts remaining in batch", remaining);
+            }
+        });
+        gitlab_issues.into_iter().filter_map(|i| i).collect()
+    }
+
+    /**
+     * Remove the files downloaded for a batch of artifacts
+     * @param artifacts the batch already posted
+     */
+    pub fn clean_files(&self, artifacts: &[Value]) {
+        for artifact in artifacts {
+            let _ = remove_dir_all(format!("{}/{}", self.file_dir, artifact["id"]));
+        }
+    }
+
+    /**
+     * Retrieve everything needed for one artifact and build the gitlab issue
+     * @param tuleap the tuleap client
+     * @param issue the artifact from the tracker
+     * @return the GitlabIssue, None if tuleap failed to give everything needed
+     */
+    fn convert_artifact(&self, tuleap: &mut TuleapClient, issue: &Value) -> Option<GitlabIssue> {
+        let release_date = DateTime::parse_from_rfc3339("2017-07-21T00:00:00+02:00").unwrap();
+        let started = Instant::now();
+        // Retrieve base issue
+        let details = tuleap.get_artifact_details(issue["id"].to_string())?;
+        let title = self.clean_txt(self.rm_first_and_last(issue["title"].to_string()));
+        let created_at = self.clean_txt(self.rm_first_and_last(issue["submitted_on"].to_string()));
+        let mut project_url: String = String::from(""); // for now store platform
+        let mut labels: Vec<String> = Vec::new();
+        let mut attachments: Vec<String> = Vec::new();
+        // Downloaded files by tuleap file id
+        let mut files: HashMap<u64, String> = HashMap::new();
+        // Files referenced in the texts, with their tuleap url
+        let mut inline_files: Vec<(String, String)> = Vec::new();
+        let artifact_id = issue["id"].to_string();
+        let mut assignee: String = String::from("");
+        let mut description = String::from("Issue generated from Tuleap's migration script.\n**Originally submitted by: ");
+        let mut sender: String;
+        let mut closed = false;
+        let mut skipped_fields: Vec<String> = Vec::new();
+        let mut unmapped: Vec<String> = Vec::new();
+        let mut unmapped_platforms: Vec<String> = Vec::new();
+        let mut linked_artifacts: Vec<u64> = Vec::new();
+        // Attachments tuleap failed to give, only mentioned
+        let mut failed_downloads: Vec<String> = Vec::new();
+        let mut projects: Vec<String> = Vec::new();
+        // Index of the matching rule and its project
+        let mut routed: Option<(usize, String)> = None;
+        if details["submitted_by_details"].is_object() {
+            sender = details["submitted_by_details"]["display_name"].to_string();
+        } else {
+            sender = details["submitted_by_user"]["display_name"].to_string();
+        }
+        description += &sender[1..(sender.len()-1)];
+        description += "**";
+        let values = &details["values"];
+        if values.is_array() {
+            for v in values.as_array().unwrap() {
+                let label = &v["label"];
+                for (i, rule) in self.routing_rules.iter().enumerate() {
+                    if rule.field.matches_field(v) && rule.value.in_field(v)
+                        && routed.as_ref().map_or(true, |r| i < r.0) {
+                        routed = Some((i, rule.project.clone()));
+                    }
+                }
+                if self.fields.platform.matches_field(v) {
+                    for platform in v["values"].as_array().into_iter().flat_map(|p| p.iter()) {
+                        let found = self.project_map.iter().find(|&&(ref value, _)| match *value {
+                            TuleapRef::Id(id) => platform["id"].as_u64() == Some(id),
+                            TuleapRef::Label(ref name) => platform["label"] == **name
+                        });
+                        match found {
+                            Some(&(_, ref p)) => if !projects.contains(p) { projects.push(p.clone()) },
+                            None => unmapped_platforms.push(String::from(platform["label"].as_str().unwrap_or("")))
                         }
-                    } else if label == "Original Submission" {
-                        description += "\n\n";
-                        let mut submission = v["value"].to_string();
-                        description += &self.clean_txt(self.rm_first_and_last(submission));
-                    } else if label == "Status" {
-                        let status = v["values"][0]["label"].to_string();
-                        let status = &status[1..(status.len()-1)];
-                        closed = status == "Done" || status == "Declined";
-                        if status == "Declined" {
-                            labels.push(String::from("invalid"));
-                            info!("mark issue {} as Declined", issue["id"].as_str().unwrap_or(""));
+                    }
+                } else if self.fields.severity.matches_field(v) {
+                    for mapping in self.fields.severity_values.iter().filter(|m| m.value.in_field(v)) {
+                        if let Some(ref label) = mapping.label {
+                            IssueRetriever::add_label(&mut labels, label.clone());
+                        }
+                    }
+                } else if self.fields.description.matches_field(v) {
+                    description += "\n\n";
+                    let submission = v["value"].to_string();
+                    let submission = self.inline_files(tuleap, self.rm_first_and_last(submission),
+                                                       &*artifact_id, &mut inline_files);
+                    description += &self.clean_txt(submission);
+                } else if self.fields.status.matches_field(v) {
+                    for mapping in self.fields.status_values.iter().filter(|m| m.value.in_field(v)) {
+                        closed = closed || mapping.closed;
+                        if let Some(ref label) = mapping.label {
+                            IssueRetriever::add_label(&mut labels, label.clone());
+                            info!("mark issue {} as {}", artifact_id, label);
                         }
-                        if !closed {
-                            let date = details["last_modified_date"].to_string();
-                            let end = date.len() - 1;
-                            let date = DateTime::parse_from_rfc3339(&date[1..end]).unwrap_or(release_date);
-                            if date.timestamp() < release_date.timestamp() {
-                                labels.push(String::from("zombie"));
-                                closed = true;
-                                info!("mark issue {} as zombie", issue["id"].as_str().unwrap_or(""));
+                    }
+                    if !closed {
+                        let date = details["last_modified_date"].to_string();
+                        let end = date.len() - 1;
+                        let date = DateTime::parse_from_rfc3339(&date[1..end]).unwrap_or(release_date);
+                        if date.timestamp() < release_date.timestamp() {
+                            IssueRetriever::add_label(&mut labels, self.mapped_label("status", "zombie", "zombie"));
+                            closed = true;
+                            info!("mark issue {} as zombie", issue["id"].as_str().unwrap_or(""));
+                        }
+                    }
+                } else if self.fields.attachments.matches_field(v) {
+                    let mut files_descriptions = &v["file_descriptions"];
+                    if !files_descriptions.is_array() {
+                        continue;
+                    }
+                    for desc in files_descriptions.as_array().unwrap() {
+                        let name = self.rm_first_and_last(desc["name"].to_string());
+                        let url = self.rm_first_and_last(desc["html_url"].to_string());
+                        let path = match tuleap.get_file(url, desc["id"].as_u64(), desc["size"].as_u64(), name.clone(),
+                                                         self.file_dir.clone(), issue["id"].to_string()) {
+                            Some(path) => path,
+                            None => {
+                                warn!("Failed to get attachment {} of artifact {}", name, artifact_id);
+                                failed_downloads.push(name);
+                                continue;
                             }
+                        };
+                        if let Some(id) = desc["id"].as_u64() {
+                            files.insert(id, path.clone());
                         }
-                    } else if label == "Attachments" {
-                        let mut files_descriptions = &v["file_descriptions"];
-                        if !files_descriptions.is_array() {
-                            continue;
+                        attachments.push(path);
+                    }
+                } else if self.fields.assignee.matches_field(v) {
+                    let username = self.rm_first_and_last(v["values"][0]["username"].to_string());
+                    assignee = match self.assignees_map.get(&username) {
+                        Some(a) => a.clone(),
+                        None => {
+                            if v["values"][0].is_object() {
+                                unmapped.push(format!("user {}", username));
+                            }
+                            String::from("")
                         }
-                        for desc in files_descriptions.as_array().unwrap() {
-                            let name = self.rm_first_and_last(desc["name"].to_string());
-                            let url = self.rm_first_and_last(desc["html_url"].to_string());
-                            attachments.push(tuleap.get_file(url,
-                                                             name,
-                                                             self.file_dir.clone(),
-                                                             issue["id"].to_string())
-                                            );
+                    };
+                } else if v["type"] == "art_link" {
+                    // Linked artifacts, in both directions
+                    for link in v["links"].as_array().into_iter().chain(v["reverse_links"].as_array()).flat_map(|l| l.iter()) {
+                        if let Some(id) = link["id"].as_u64() {
+                            if !linked_artifacts.contains(&id) {
+                                linked_artifacts.push(id);
+                            }
                         }
-                    } else if label == "Assigned to" {
-                        assignee = v["values"][0]["username"].to_string();
-                        assignee = match self.assignees_map.get(&self.rm_first_and_last(assignee)) {
-                            Some(a) => a.clone(),
-                            None => String::from("")
-                        };
                     }
+                } else if self.has_value(v) && !self.routing_rules.iter().any(|r| r.field.matches_field(v))
+                    && !self.fields.title.matches_field(v)
+                    && !SYSTEM_FIELD_TYPES.contains(&v["type"].as_str().unwrap_or("")) {
+                    skipped_fields.push(String::from(label.as_str().unwrap_or("")));
                 }
             }
-            // Retrieve comments
-            let comments_json = tuleap.get_artifact_comments(issue["id"].to_string());
-            let mut comments: Vec<GitlabComment> = Vec::new();
-            for comment in comments_json {
-                let mut comment_txt: String = String::from("**Submitted by ");
-                if comment["submitted_by_details"].is_object() {
-                    sender = comment["submitted_by_details"]["display_name"].to_string();
-                } else {
-                    sender = comment["submitted_by_user"]["display_name"].to_string();
+        }
+        // Retrieve comments
+        let comments_json = tuleap.get_artifact_comments(issue["id"].to_string())?;
+        let mut comments: Vec<GitlabComment> = Vec::new();
+        let mut known_files: Vec<u64> = Vec::new();
+        for (i, comment) in comments_json.iter().enumerate() {
+            // Files which appear in this changeset, those of the submission stay in the description
+            let mut comment_files: Vec<String> = Vec::new();
+            for v in comment["values"].as_array().into_iter().flat_map(|v| v.iter()) {
+                if !self.fields.attachments.matches_field(v) {
+                    continue;
+                }
+                for desc in v["file_descriptions"].as_array().into_iter().flat_map(|d| d.iter()) {
+                    let id = desc["id"].as_u64().unwrap_or(0);
+                    if known_files.contains(&id) {
+                        continue;
+                    }
+                    known_files.push(id);
+                    if let Some(path) = files.get(&id) {
+                        if i > 0 {
+                            comment_files.push(path.clone());
+                        }
+                    }
                 }
-                comment_txt += &sender[1..(sender.len()-1)];
-                comment_txt += "**\n\n";
-                let mut body = comment["last_comment"]["body"].to_string();
-                comment_txt += &self.clean_txt(self.rm_first_and_last(body));
-                comments.push(GitlabComment {
-                    body: comment_txt,
-                    created_at: self.rm_first_and_last(comment["submitted_on"].to_string())
-                })
             }
-            info!("New issue generated {}", title);
-            debug!("{}", issue);
-            let issue = GitlabIssue {
-                title: title,
-                closed: closed,
-                description: description,
-                assignee: assignee.clone(),
-                labels: labels,
-                project_url: project_url,
-                created_at: created_at,
-                comments: comments,
-                attachments: attachments.clone()
-            };
-            gitlab_issues.push(issue);
+            let comment_body = comment["last_comment"]["body"].as_str().unwrap_or("");
+            // Already on gitlab
+            if comment_body.starts_with(MIRROR_MARKER) {
+                continue;
+            }
+            // Field changes only, nothing to say
+            if comment_body.trim().is_empty() && comment_files.is_empty() {
+                continue;
+            }
+            let mut comment_txt: String = String::from("**Submitted by ");
+            if comment["submitted_by_details"].is_object() {
+                sender = comment["submitted_by_details"]["display_name"].to_string();
+            } else {
+                sender = comment["submitted_by_user"]["display_name"].to_string();
+            }
+            comment_txt += &sender[1..(sender.len()-1)];
+            comment_txt += "**\n\n";
+            let body = comment["last_comment"]["body"].to_string();
+            let body = self.inline_files(tuleap, self.rm_first_and_last(body), &*artifact_id, &mut inline_files);
+            comment_txt += &self.clean_txt(body);
+            comments.push(GitlabComment {
+                changeset_id: comment["id"].as_u64().unwrap_or(0),
+                body: comment_txt,
+                created_at: self.rm_first_and_last(comment["submitted_on"].to_string()),
+                attachments: comment_files
+            })
+        }
+        // A routing rule replaces the platforms
+        if let Some((_, project)) = routed {
+            projects = vec![project];
+            unmapped_platforms.clear();
+        }
+        for platform in unmapped_platforms.iter() {
+            unmapped.push(format!("platform {}", platform));
+        }
+        if !projects.is_empty() {
+            project_url = projects.remove(0);
+        } else {
+            if unmapped_platforms.is_empty() {
+                unmapped.push(String::from("platform (none)"));
+            }
+            let platform = unmapped_platforms.join(", ");
+            match self.unmapped_policy {
+                UnmappedPolicy::Default(ref project) => project_url = project.clone(),
+                UnmappedPolicy::Triage { ref project, ref label } => {
+                    project_url = project.clone();
+                    IssueRetriever::add_label(&mut labels, label.clone());
+                    description += &*format!("\n\n**Tuleap platform: {}**", platform);
+                },
+                UnmappedPolicy::Skip => info!("No gitlab project for {}, skip it", title)
+            }
+        }
+        for label in self.extra_labels.iter() {
+            IssueRetriever::add_label(&mut labels, label.clone());
+        }
+        for name in failed_downloads.iter() {
+            description += &*format!("  \n*{} could not be migrated*", name);
         }
-        gitlab_issues
+        info!("New issue generated {}", title);
+        debug!("{}", issue);
+        Some(GitlabIssue {
+            tuleap_id: issue["id"].as_u64().unwrap_or(0),
+            title: title,
+            closed: closed,
+            description: description,
+            assignee: assignee.clone(),
+            labels: labels,
+            project_url: project_url,
+            created_at: created_at,
+            comments: comments,
+            attachments: attachments.clone(),
+            inline_files: inline_files,
+            failed_downloads: failed_downloads,
+            linked_artifacts: linked_artifacts,
+            related: match self.multi_platform_policy {
+                MultiPlatformPolicy::Links => RelatedProjects::Links(projects),
+                MultiPlatformPolicy::Copies => RelatedProjects::Copies(projects)
+            },
+            skipped_fields: skipped_fields,
+            unmapped: unmapped,
+            retrieve_time: started.elapsed()
+        })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{InlineReference, TuleapRef, ValueMapping};
+
+    /**
+     * @param text with a reference
+     * @return the replaced part of the text, path, file id, name and url_only of the first reference
+     */
+    fn reference(text: &str) -> (String, String, Option<u64>, String, bool) {
+        let r = InlineReference::find(text).expect("no reference found");
+        (String::from(&text[r.start..r.end]), r.path, r.file_id, r.name, r.url_only)
+    }
+
+    #[test]
+    fn inline_reference_relative() {
+        assert_eq!(reference("see /plugins/tracker/attachments/12-log.txt please"),
+                   (String::from("/plugins/tracker/attachments/12-log.txt"),
+                    String::from("/plugins/tracker/attachments/12-log.txt"), Some(12), String::from("log.txt"), false));
+    }
+
+    #[test]
+    fn inline_reference_absolute() {
+        let (text, path, file_id, name, url_only) =
+            reference("see https://tuleap.example.com/plugins/tracker/attachments/12-my-log.txt?preview=1.");
+        assert_eq!(text, "https://tuleap.example.com/plugins/tracker/attachments/12-my-log.txt?preview=1");
+        assert_eq!(path, "/plugins/tracker/attachments/12-my-log.txt?preview=1");
+        assert_eq!((file_id, &*name, url_only), (Some(12), "my-log.txt", false));
+    }
+
+    #[test]
+    fn inline_reference_markdown() {
+        let (text, _, file_id, name, url_only) = reference("before ![shot](/plugins/tracker/attachments/7-shot.png) after");
+        assert_eq!((&*text, file_id, &*name, url_only), ("![shot](/plugins/tracker/attachments/7-shot.png)", Some(7), "shot.png", false));
+        let (text, _, _, _, url_only) = reference("[the doc](https://tuleap/plugins/tracker/attachments/8-doc.pdf)");
+        assert_eq!((&*text, url_only), ("[the doc](https://tuleap/plugins/tracker/attachments/8-doc.pdf)", false));
+    }
+
+    #[test]
+    fn inline_reference_html() {
+        let (text, _, file_id, _, url_only) = reference("<img src=\"/plugins/tracker/attachments/7-shot.png\" alt=\"\">");
+        assert_eq!((&*text, file_id, url_only), ("/plugins/tracker/attachments/7-shot.png", Some(7), true));
+        // Quotes escaped in the raw json text
+        let (text, _, _, _, url_only) = reference("<img SRC=\\\"https://tuleap/plugins/tracker/attachments/7-shot.png\\\">");
+        assert_eq!((&*text, url_only), ("https://tuleap/plugins/tracker/attachments/7-shot.png", true));
+        let (text, _, _, _, url_only) = reference("<a href='/plugins/tracker/attachments/8-doc.pdf'>doc</a>");
+        assert_eq!((&*text, url_only), ("/plugins/tracker/attachments/8-doc.pdf", true));
+        let (_, _, _, _, url_only) = reference("<p>\"/plugins/tracker/attachments/8-doc.pdf\"</p>");
+        assert!(!url_only);
+    }
+
+    #[test]
+    fn inline_reference_without_name() {
+        let (_, _, file_id, name, _) = reference("/plugins/tracker/attachments/ is the folder");
+        assert_eq!((file_id, &*name), (None, ""));
+        assert!(InlineReference::find("no attachment here").is_none());
+    }
+
+    fn tracker() -> ::serde_json::Value {
+        json!({"id": 101, "fields": [
+            {"field_id": 10, "label": "Gravit\u{e9}", "values": [
+                {"id": 100, "label": "1 - Ordinaire"},
+                {"id": 101, "label": "3 - Moyenne"},
+                {"id": 105, "label": "5 - Majeure"},
+                {"id": 109, "label": "9 - Critique"}
+            ]},
+            {"field_id": 11, "label": "Status", "values": [{"id": 200, "label": "Done"}]}
+        ]})
+    }
+
+    #[test]
+    fn default_severities_match_the_first_character() {
+        let mappings = ValueMapping::default_severities(false, &tracker(), &TuleapRef::Id(10));
+        let found: Vec<(u64, String)> = mappings.iter().map(|m| match m.value {
+            TuleapRef::Id(id) => (id, m.label.clone().unwrap()),
+            TuleapRef::Label(_) => panic!("value not resolved")
+        }).collect();
+        assert_eq!(found, vec![(100, String::from("S - Ordinary")), (105, String::from("S - Major")),
+                               (109, String::from("S - Critical"))]);
+    }
+
+    #[test]
+    fn default_severities_without_tracker() {
+        let mappings = ValueMapping::default_severities(true, &json!(null), &TuleapRef::Id(10));
+        assert!(mappings[0].value == TuleapRef::Label(String::from("1 - Ordinary")));
+        assert_eq!(mappings[0].label, Some(String::from("severity::ordinary")));
+        assert!(ValueMapping::default_severities(false, &tracker(), &TuleapRef::Id(11)).is_empty());
+    }
+
+    #[test]
+    fn resolve_value() {
+        let status = TuleapRef::Label(String::from("Status"));
+        assert!(TuleapRef::Label(String::from("Done")).resolve_value(&tracker(), &status) == TuleapRef::Id(200));
+        assert!(TuleapRef::Label(String::from("Termin\u{e9}")).resolve_value(&tracker(), &status)
+                == TuleapRef::Label(String::from("Termin\u{e9}")));
+        assert!(TuleapRef::Id(5).resolve_value(&tracker(), &status) == TuleapRef::Id(5));
+    }
+}
+
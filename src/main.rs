@@ -1,21 +1,495 @@
 extern crate chrono;
 extern crate reqwest;
-extern crate serde_json;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate log;
 extern crate env_logger;
+extern crate hmac;
+extern crate sha2;
+extern crate url;
 
+mod checksum;
+mod discover;
+mod filter;
 mod gitlabclient;
+mod idmap;
+mod manifest;
 mod issueretriever;
+mod report;
 mod tuleapclient;
+mod webhook;
 
-use gitlabclient::GitlabClient;
-use issueretriever::IssueRetriever;
-use serde_json::{Value, from_str};
+use chrono::prelude::*;
+use discover::discover;
+use filter::ArtifactFilter;
+use gitlabclient::{CreationOrder, GitlabClient, GitlabIssue};
+use idmap::{IdMap, MappedIssue};
+use manifest::Manifest;
+use issueretriever::{FieldMap, IssueRetriever, MultiPlatformPolicy, RoutingRule, TuleapRef, UnmappedPolicy, ValueMapping};
+use report::{ArtifactReport, Outcome, Report};
+use serde_json::{Value, from_str, to_string_pretty};
 use std::env;
-use std::fs::File;
+use std::fs::{File, create_dir_all};
 use std::collections::HashMap;
 use std::io::prelude::*;
-use tuleapclient::TuleapClient;
+use std::process;
+use std::time::Instant;
+use tuleapclient::{CacheMode, MIRROR_MARKER, TuleapClient};
+use webhook::WebhookServer;
+
+/**
+ * Check if an artifact changed since the last run
+ * @param artifact from the tracker
+ * @param last_run date of the last run, if any
+ * @return true if modified after last_run (or if unknown)
+ */
+fn modified_since(artifact: &Value, last_run: &Option<String>) -> bool {
+    let last_run = match *last_run {
+        Some(ref last_run) => DateTime::parse_from_rfc3339(&*last_run),
+        None => return true
+    };
+    let modified = DateTime::parse_from_rfc3339(artifact["last_modified_date"].as_str().unwrap_or(""));
+    match (modified, last_run) {
+        (Ok(modified), Ok(last_run)) => modified > last_run,
+        _ => true
+    }
+}
+
+/**
+ * Read a field from the config and use its id
+ * @param config the field, by id or label
+ * @param default label of the field if config is missing
+ * @param structure of the tracker
+ * @return the field
+ */
+fn load_field(config: &Value, default: &TuleapRef, structure: &Value) -> TuleapRef {
+    TuleapRef::from_config(config).unwrap_or(default.clone()).resolve_field(structure)
+}
+
+/**
+ * Read what values of a field give on gitlab and use their ids
+ * @param config the values, each with a value (by id or label), an optional label and closed
+ * @param default mapping if config is missing
+ * @param structure of the tracker
+ * @param field holding the values
+ * @return the mapping
+ */
+fn load_values(config: &Value, default: Vec<ValueMapping>, structure: &Value, field: &TuleapRef) -> Vec<ValueMapping> {
+    let mappings = match config.as_array() {
+        Some(values) => values.iter().filter_map(|v| Some(ValueMapping {
+            value: TuleapRef::from_config(&v["value"])?,
+            label: v["label"].as_str().map(String::from),
+            closed: v["closed"].as_bool().unwrap_or(false)
+        })).collect(),
+        None => default
+    };
+    mappings.into_iter().map(|m| ValueMapping {
+        value: m.value.resolve_value(structure, field),
+        ..m
+    }).collect()
+}
+
+/**
+ * Read the artifacts to migrate from the config
+ * @param filter the filter section of the config
+ * @param structure of the tracker, to resolve fields
+ * @return the filter, matching every artifact if the section is missing
+ */
+fn load_filter(filter: &Value, structure: &Value) -> ArtifactFilter {
+    let date = |key: &str| filter[key].as_str().map(|d| {
+        ArtifactFilter::parse_date(d).expect(&*format!("Incorrect date for {}", key))
+    });
+    ArtifactFilter {
+        ids: filter["ids"].as_array().map_or(Vec::new(), |ids| ids.iter().filter_map(|id| id.as_u64()).collect()),
+        id_min: filter["id_min"].as_u64(),
+        id_max: filter["id_max"].as_u64(),
+        statuses: filter["status"].as_array().map_or(Vec::new(), |s| {
+            s.iter().filter_map(|s| s.as_str()).map(String::from).collect()
+        }),
+        submitted_after: date("submitted_after"),
+        submitted_before: date("submitted_before"),
+        modified_after: date("modified_after"),
+        modified_before: date("modified_before"),
+        fields: filter["fields"].as_array().map_or(Vec::new(), |f| f.iter().map(|f| {
+            let field = load_field(&f["field"], &TuleapRef::Label(String::new()), structure);
+            let value = TuleapRef::from_config(&f["value"]).unwrap_or(TuleapRef::Label(String::new()))
+                .resolve_value(structure, &field);
+            (field, value)
+        }).collect())
+    }
+}
+
+/**
+ * A tracker to migrate, with its own mapping
+ */
+struct Tracker {
+    id: u64,
+    client: TuleapClient,
+    retriever: IssueRetriever,
+    filter: ArtifactFilter
+}
+
+/**
+ * @param config the whole config
+ * @param tracker a tracker definition
+ * @param key to read
+ * @return the value of the tracker, or of the config if the tracker doesn't set it
+ */
+fn tracker_value<'a>(config: &'a Value, tracker: &'a Value, key: &str) -> &'a Value {
+    if tracker[key].is_null() { &config[key] } else { &tracker[key] }
+}
+
+/**
+ * Read the mapping of a tracker and resolve its projects
+ * @param gc the gitlab client
+ * @param tc the tuleap client
+ * @param id_map where resolved projects are stored
+ * @param config the whole config
+ * @param tracker the tracker definition
+ * @param assignees_map tuleap username to gitlab user id
+ * @param label_projects where projects of the tracker are added
+ * @return the tracker
+ */
+fn load_tracker(gc: &GitlabClient, tc: &TuleapClient, id_map: &mut IdMap, config: &Value, tracker: &Value,
+                assignees_map: &HashMap<String, String>, label_projects: &mut Vec<String>) -> Tracker {
+    let id = tracker["id"].as_u64().unwrap_or(0);
+    let mut client = tc.with_tracker(id);
+    // Fields and values are matched by id, so they can be renamed or translated
+    let structure = client.get_tracker(id);
+    let names = &tracker_value(config, tracker, "fields");
+    let default = FieldMap::default();
+    let scoped_labels = config["scoped_labels"].as_bool().unwrap_or(false);
+    let severity = load_field(&names["severity"], &default.severity, &structure);
+    let status = load_field(&names["status"], &default.status, &structure);
+    let fields = FieldMap {
+        // The field of the title semantic
+        title: structure["semantics"]["title"]["field_id"].as_u64().map_or(default.title.clone(), TuleapRef::Id),
+        platform: load_field(tracker_value(config, tracker, "platform_field"), &default.platform, &structure),
+        description: load_field(&names["description"], &default.description, &structure),
+        attachments: load_field(&names["attachments"], &default.attachments, &structure),
+        assignee: load_field(&names["assignee"], &default.assignee, &structure),
+        severity_values: load_values(&names["severity_values"],
+                                     ValueMapping::default_severities(scoped_labels, &structure, &severity),
+                                     &structure, &severity),
+        status_values: load_values(&names["status_values"], ValueMapping::default_statuses(scoped_labels),
+                                   &structure, &status),
+        severity: severity,
+        status: status
+    };
+    let mut projects_map = Vec::new();
+    if let Some(projects) = tracker_value(config, tracker, "projects").as_array() {
+        for p in projects {
+            let platform = match TuleapRef::from_config(&p["platform"]) {
+                Some(platform) => platform.resolve_value(&structure, &fields.platform),
+                None => continue
+            };
+            projects_map.push((platform, resolve_project(gc, id_map, config, p)));
+        }
+    }
+
+    let mut routing_rules = Vec::new();
+    if let Some(rules) = tracker_value(config, tracker, "routing").as_array() {
+        for r in rules {
+            let field = load_field(&r["field"], &TuleapRef::Label(String::new()), &structure);
+            routing_rules.push(RoutingRule {
+                value: TuleapRef::from_config(&r["value"]).unwrap_or(TuleapRef::Label(String::new()))
+                    .resolve_value(&structure, &field),
+                field: field,
+                project: resolve_project(gc, id_map, config, r)
+            });
+        }
+    }
+    let unmapped = tracker_value(config, tracker, "unmapped_platform");
+    let unmapped_project = resolve_project(gc, id_map, config, unmapped);
+    let unmapped_policy = match unmapped["policy"].as_str() {
+        Some("default") => UnmappedPolicy::Default(unmapped_project.clone()),
+        Some("triage") => UnmappedPolicy::Triage {
+            project: unmapped_project.clone(),
+            label: String::from(unmapped["label"].as_str().unwrap_or("needs triage"))
+        },
+        _ => UnmappedPolicy::Skip
+    };
+
+    let multi_platform_policy = match tracker_value(config, tracker, "multi_platform").as_str() {
+        Some("copies") => MultiPlatformPolicy::Copies,
+        _ => MultiPlatformPolicy::Links
+    };
+
+    label_projects.extend(projects_map.iter().map(|p| p.1.clone()));
+    label_projects.extend(routing_rules.iter().map(|r| r.project.clone()));
+    if !unmapped_project.is_empty() {
+        label_projects.push(unmapped_project);
+    }
+    // Only set by trackers, labels of the config are label definitions
+    let extra_labels: Vec<String> = tracker["extra_labels"].as_array().map_or(Vec::new(), |l| {
+        l.iter().filter_map(|l| l.as_str()).map(String::from).collect()
+    });
+
+    Tracker {
+        id: id,
+        client: client,
+        retriever: IssueRetriever::new(assignees_map.clone(), projects_map, fields,
+                                       routing_rules, unmapped_policy, multi_platform_policy,
+                                       scoped_labels, extra_labels,
+                                       String::from(config["file_dir"].as_str().unwrap_or("data")),
+                                       config["tuleap_workers"].as_u64().unwrap_or(4) as usize),
+        filter: load_filter(tracker_value(config, tracker, "filter"), &structure)
+    }
+}
+
+/**
+ * Post new gitlab notes and state changes of migrated issues as tuleap comments
+ * @param gc the gitlab client
+ * @param tc the tuleap client, with an access key
+ * @param id_map migrated artifacts, updated with what was mirrored
+ * @note notes from the migration account are ignored, they come from tuleap
+ */
+fn mirror_to_tuleap(gc: &GitlabClient, tc: &mut TuleapClient, id_map: &mut IdMap) {
+    let bot = gc.current_user_id().expect("Failed to get the gitlab user");
+    for tuleap_id in id_map.ids() {
+        let mut mapped = id_map.get(tuleap_id).unwrap().clone();
+        let issue = match gc.get_issue(&*mapped.project, mapped.iid) {
+            Some(issue) => issue,
+            None => {
+                warn!("Issue {}/{} not found", mapped.project, mapped.iid);
+                continue;
+            }
+        };
+        for note in gc.get_notes(&*mapped.project, mapped.iid) {
+            let id = note["id"].as_u64().unwrap_or(0);
+            if id <= mapped.last_note || note["system"].as_bool().unwrap_or(false)
+                || note["author"]["id"].as_u64() == Some(bot) {
+                continue;
+            }
+            let body = format!("{} {} commented on {}:\n\n{}", MIRROR_MARKER,
+                               note["author"]["name"].as_str().unwrap_or(""),
+                               issue["web_url"].as_str().unwrap_or(""),
+                               note["body"].as_str().unwrap_or(""));
+            info!("Mirror note {} to artifact {}", id, tuleap_id);
+            if !tc.post_comment(tuleap_id, body) {
+                break;
+            }
+            mapped.last_note = id;
+        }
+        let closed = issue["state"].as_str() == Some("closed");
+        if closed != mapped.mirrored_closed {
+            let body = format!("{} Issue {} on {}", MIRROR_MARKER,
+                               if closed { "closed" } else { "reopened" },
+                               issue["web_url"].as_str().unwrap_or(""));
+            info!("Mirror state of artifact {}", tuleap_id);
+            if tc.post_comment(tuleap_id, body) {
+                mapped.mirrored_closed = closed;
+            }
+        }
+        id_map.insert(tuleap_id, mapped);
+    }
+}
+
+/**
+ * Get the id of a project from the config
+ * @param gc the gitlab client
+ * @param id_map where ids resolved from a path are stored
+ * @param config the whole config
+ * @param entry the project in the config. gitlab_id is an id, or a path like group/sub/project
+ * (relative to gitlab_group if it contains no /)
+ * @return the id of the project, empty if not found
+ * @note with provision_projects, missing projects are created and existing ones get their
+ * issues enabled
+ */
+fn resolve_project(gc: &GitlabClient, id_map: &mut IdMap, config: &Value, entry: &Value) -> String {
+    let gitlab_id = entry["gitlab_id"].as_str().unwrap_or("");
+    if gitlab_id.chars().all(|c| c.is_ascii_digit()) {
+        return String::from(gitlab_id);
+    }
+    let group = config["gitlab_group"].as_str().unwrap_or("");
+    let path = if gitlab_id.contains('/') || group.is_empty() {
+        String::from(gitlab_id)
+    } else {
+        format!("{}/{}", group, gitlab_id)
+    };
+    let provision = config["provision_projects"].as_bool().unwrap_or(false);
+    if !provision {
+        if let Some(id) = id_map.project(&*path) {
+            return id;
+        }
+    }
+    let id = match gc.get_project_id(&*path) {
+        Some(id) => {
+            // The visibility of existing projects is only changed when asked for this project
+            if provision {
+                gc.update_project(id, entry["visibility"].as_str());
+            }
+            Some(id)
+        },
+        None if provision => {
+            let name = entry["name"].as_str().unwrap_or(path.split('/').last().unwrap_or(""));
+            let visibility = entry["visibility"].as_str()
+                             .unwrap_or(config["project_visibility"].as_str().unwrap_or("private"));
+            gc.create_project(&*path, name, visibility)
+        },
+        None => None
+    };
+    match id {
+        Some(id) => {
+            id_map.set_project(path, id.to_string());
+            id.to_string()
+        },
+        None => {
+            warn!("Project {} not found", path);
+            String::new()
+        }
+    }
+}
+
+/**
+ * Compare each migrated artifact with gitlab and print a discrepancy report
+ * @param gc the gitlab client
+ * @param id_map migrated artifacts
+ * @return true if gitlab holds what was migrated
+ */
+fn verify_migration(gc: &GitlabClient, id_map: &IdMap) -> bool {
+    let bot = gc.current_user_id().expect("Failed to get the gitlab user");
+    let ids = id_map.ids();
+    let mut failed = 0;
+    for tuleap_id in ids.iter() {
+        let mapped = id_map.get(*tuleap_id).unwrap();
+        let discrepancies = gc.verify_issue(mapped, bot);
+        if discrepancies.is_empty() {
+            continue;
+        }
+        failed += 1;
+        println!("Artifact {} ({}#{}):", tuleap_id, mapped.project, mapped.iid);
+        for d in discrepancies {
+            println!("  - {}", d);
+        }
+    }
+    println!("{} artifacts verified, {} with discrepancies", ids.len(), failed);
+    failed == 0
+}
+
+/**
+ * Migrate a batch of artifacts and record them in the id map and the report
+ * @param gc the gitlab client
+ * @param tc the tuleap client
+ * @param retriever used to convert artifacts
+ * @param id_map migrated artifacts
+ * @param report of the migration
+ * @param artifacts the batch to migrate
+ * @param update if already migrated artifacts are updated instead of created again
+ * @param workers number of issues posted in parallel
+ * @param order in which order issues are created
+ * @param labels_group group holding labels, empty if labels are per project
+ */
+fn migrate_artifacts(gc: &GitlabClient, tc: &TuleapClient, retriever: &IssueRetriever,
+                     id_map: &mut IdMap, report: &mut Report, artifacts: &[Value], update: bool,
+                     workers: usize, order: CreationOrder, labels_group: &str) {
+    let mut gitlab_issues = retriever.tuleap_to_gitlab(tc, artifacts);
+    // Artifacts tuleap failed to give, the next sync retries them
+    for artifact in artifacts {
+        let tuleap_id = artifact["id"].as_u64().unwrap_or(0);
+        if gitlab_issues.iter().any(|i| i.tuleap_id == tuleap_id) {
+            continue;
+        }
+        report.add(ArtifactReport {
+            tuleap_id: tuleap_id,
+            title: String::from(artifact["title"].as_str().unwrap_or("")),
+            outcome: Outcome::Failed,
+            url: String::new(),
+            related_urls: Vec::new(),
+            skipped_fields: Vec::new(),
+            unmapped: Vec::new(),
+            attachment_failures: Vec::new(),
+            failed_comments: 0,
+            retrieve_ms: 0,
+            post_ms: 0
+        });
+    }
+    // Artifacts without project, according to the unmapped platform policy
+    gitlab_issues.retain(|i| {
+        if !i.project_url.is_empty() || (update && id_map.get(i.tuleap_id).is_some()) {
+            return true;
+        }
+        report.add(ArtifactReport {
+            tuleap_id: i.tuleap_id,
+            title: i.title.clone(),
+            outcome: Outcome::Skipped,
+            url: String::new(),
+            related_urls: Vec::new(),
+            skipped_fields: i.skipped_fields.clone(),
+            unmapped: i.unmapped.clone(),
+            attachment_failures: Vec::new(),
+            failed_comments: 0,
+            retrieve_ms: i.retrieve_time.as_millis() as u64,
+            post_ms: 0
+        });
+        false
+    });
+    gc.ensure_labels(&gitlab_issues, labels_group);
+    let (existing, new): (Vec<GitlabIssue>, Vec<GitlabIssue>) = gitlab_issues.into_iter().partition(|i| {
+        update && id_map.get(i.tuleap_id).is_some()
+    });
+
+    info!("Create gitlab issues");
+    let results = gc.generate_issues(&new, workers, order);
+    for (issue, result) in new.iter().zip(results) {
+        if let Some(iid) = result.iid {
+            // Links to artifacts not migrated yet are made when they are, from their side
+            for linked in issue.linked_artifacts.iter().filter_map(|id| id_map.get(*id)) {
+                gc.link_issues(&*issue.project_url, iid, &*linked.project, linked.iid);
+            }
+            id_map.insert(issue.tuleap_id, MappedIssue {
+                project: issue.project_url.clone(),
+                iid: iid,
+                web_url: result.web_url.clone(),
+                title: issue.title.clone(),
+                labels: issue.labels.clone(),
+                assignee: issue.assignee.clone(),
+                comments: issue.comments.len() - result.failed_comments,
+                last_changeset: result.last_changeset,
+                // Inline files are uploaded too, failed files are posted again by the next sync
+                attachments: issue.attachments.iter().chain(issue.inline_files.iter().map(|f| &f.0))
+                    .filter(|a| !result.failed_attachments.contains(a)).cloned().collect(),
+                uploads: result.uploads,
+                closed: issue.closed,
+                mirrored_closed: issue.closed,
+                last_note: 0
+            });
+        }
+        report.add(ArtifactReport {
+            tuleap_id: issue.tuleap_id,
+            title: issue.title.clone(),
+            outcome: if result.iid.is_some() { Outcome::Created } else { Outcome::Failed },
+            url: result.web_url,
+            related_urls: result.related_urls,
+            skipped_fields: issue.skipped_fields.clone(),
+            unmapped: issue.unmapped.clone(),
+            attachment_failures: issue.failed_downloads.iter().cloned().chain(result.failed_attachments).collect(),
+            failed_comments: result.failed_comments,
+            retrieve_ms: issue.retrieve_time.as_millis() as u64,
+            post_ms: result.post_time.as_millis() as u64
+        });
+    }
+    for issue in existing {
+        let started = Instant::now();
+        let mapped = id_map.get(issue.tuleap_id).unwrap().clone();
+        let updated = gc.update_issue(&issue, &mapped);
+        report.add(ArtifactReport {
+            tuleap_id: issue.tuleap_id,
+            title: issue.title.clone(),
+            outcome: Outcome::Updated,
+            url: updated.web_url.clone(),
+            related_urls: Vec::new(),
+            skipped_fields: issue.skipped_fields.clone(),
+            unmapped: issue.unmapped.clone(),
+            attachment_failures: issue.failed_downloads.iter().chain(issue.attachments.iter()
+                                       .filter(|a| !updated.attachments.contains(a)))
+                                       .cloned().collect(),
+            failed_comments: issue.new_comments(&updated).len(),
+            retrieve_ms: issue.retrieve_time.as_millis() as u64,
+            post_ms: started.elapsed().as_millis() as u64
+        });
+        id_map.insert(issue.tuleap_id, updated);
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -31,18 +505,64 @@ fn main() {
                         .expect("Incorrect config file. Please check config.json");
 
     let gc = GitlabClient::new(String::from(config["gitlab_url"].as_str().unwrap_or("")),
-                               String::from(config["gitlab_token"].as_str().unwrap_or("")));
+                               String::from(config["gitlab_token"].as_str().unwrap_or("")),
+                               config["gitlab_max_attachment_mb"].as_u64().unwrap_or(100) * 1024 * 1024,
+                               String::from(config["attachment_archive_dir"].as_str().unwrap_or("")),
+                               String::from(config["attachment_archive_url"].as_str().unwrap_or("")));
+    let mut id_map = IdMap::load(String::from(config["id_map"].as_str().unwrap_or("idmap.json")));
     let index = args.iter().position(|ref s| *s == "clean");
     if let Some(_) = index {
         info!("Cleaning all issues");
         gc.delete_all_issues();
+        id_map.clear();
+        id_map.save();
+        return;
+    }
+    if args.iter().any(|s| s == "verify") {
+        info!("Verify migrated issues");
+        if !verify_migration(&gc, &id_map) {
+            process::exit(1);
+        }
         return;
     }
+    let sync = args.iter().any(|s| s == "sync");
+    // Changes made during this run will be synced by the next one
+    let run_started = Utc::now().to_rfc3339();
 
     let tuleap_url = String::from(config["tuleap_url"].as_str().unwrap_or(""));
+    let file_dir = String::from(config["file_dir"].as_str().unwrap_or("data"));
+    let _ = create_dir_all(&*file_dir);
     let tuleap_tracker = config["tuleap_tracker"].as_u64().unwrap_or(0);
-    info!("Will retrieve tuleap issues from {} tracker {}", tuleap_url, tuleap_tracker);
-    let mut tc = TuleapClient::new(tuleap_url, tuleap_tracker);
+    info!("Will retrieve tuleap issues from {}", tuleap_url);
+    let mut tc = TuleapClient::new(tuleap_url, tuleap_tracker,
+                                   config["tuleap_max_retries"].as_u64().unwrap_or(3) as u32,
+                                   config["tuleap_rate_limit_ms"].as_u64().unwrap_or(0),
+                                   String::from(config["tuleap_access_key"].as_str().unwrap_or("")),
+                                   Manifest::load(format!("{}/manifest.json", file_dir)),
+                                   match config["tuleap_cache_mode"].as_str().unwrap_or("off") {
+                                       "record" => CacheMode::Record,
+                                       "replay" => CacheMode::Replay,
+                                       _ => CacheMode::Off
+                                   },
+                                   String::from(config["tuleap_cache_dir"].as_str().unwrap_or("tuleap_cache")));
+    if let Some(index) = args.iter().position(|s| s == "discover") {
+        let project = args.get(index + 1).and_then(|p| p.parse().ok())
+            .or(config["tuleap_project"].as_u64())
+            .expect("Usage: discover <tuleap project id>");
+        info!("Discover trackers of tuleap project {}", project);
+        let starter = discover(&mut tc, project);
+        let path = String::from(config["discover_output"].as_str().unwrap_or("discover.json"));
+        let mut file = File::create(&*path).ok().expect("Failed to create starter config");
+        file.write_all(to_string_pretty(&starter).unwrap().as_bytes()).ok().expect("Failed to write starter config");
+        println!("Starter mapping written to {}", path);
+        return;
+    }
+    if args.iter().any(|s| s == "mirror") {
+        info!("Mirror gitlab activity to tuleap");
+        mirror_to_tuleap(&gc, &mut tc, &mut id_map);
+        id_map.save();
+        return;
+    }
 
     let mut assignees_map = HashMap::new();
     let assignees = &config["assignees"];
@@ -58,33 +578,104 @@ fn main() {
         _ => {}
     }
 
-    let mut projects_map = HashMap::new();
-    let projects = &config["projects"];
-    match projects.as_array() {
-        Some(projects) => {
-            for p in projects {
-                projects_map.insert(
-                    String::from(p["platform"].as_str().unwrap_or("")),
-                    String::from(p["gitlab_id"].as_str().unwrap_or(""))
-                );
-            }
-        },
-        _ => {}
-    }
-
+    // A single tracker can be given with tuleap_tracker
+    let tracker_definitions = match config["trackers"].as_array() {
+        Some(trackers) => trackers.clone(),
+        None => vec![json!({ "id": config["tuleap_tracker"] })]
+    };
+    let mut label_projects: Vec<String> = Vec::new();
+    let trackers: Vec<Tracker> = tracker_definitions.iter().map(|t| {
+        load_tracker(&gc, &tc, &mut id_map, &config, t, &assignees_map, &mut label_projects)
+    }).collect();
+    id_map.save();
+    label_projects.sort();
+    label_projects.dedup();
     let labels = &config["labels"];
-
-    info!("Get interresting issues and build issues for gitlab");
-    let retriever = IssueRetriever::new(tc.get_artifacts(),
-                                        assignees_map, projects_map.clone(),
-                                        String::from(config["file_dir"].as_str().unwrap_or("")));
-    let gitlab_issues = retriever.tuleap_to_gitlab(tc);
     info!("Create gitlab labels");
-    gc.generate_labels(projects_map, labels);
+    let labels_group = String::from(config["labels_group"].as_str().unwrap_or(""));
+    gc.generate_labels(&label_projects, &*labels_group, labels);
 
-    info!("Create gitlab issues");
-    for issue in gitlab_issues {
-        // TODO move into thread
-        gc.generate_issue(&issue);
+    let order = if config["preserve_iids"].as_bool().unwrap_or(false) {
+        CreationOrder::ArtifactId {
+            delete_placeholders: config["delete_placeholders"].as_bool().unwrap_or(false),
+            max_gap: config["max_placeholder_gap"].as_u64().unwrap_or(1000)
+        }
+    } else if config["gitlab_ordered"].as_bool().unwrap_or(false) {
+        CreationOrder::CreationDate
+    } else {
+        CreationOrder::Unordered
+    };
+    let gitlab_workers = config["gitlab_workers"].as_u64().unwrap_or(4) as usize;
+    let mut report = Report::new(String::from(config["report"].as_str().unwrap_or("report")));
+
+    if args.iter().any(|s| s == "serve") {
+        let secret = String::from(config["webhook_secret"].as_str().unwrap_or(""));
+        if secret.is_empty() {
+            error!("webhook_secret is needed to check the signature of webhooks");
+            process::exit(1);
+        }
+        let server = WebhookServer::new(String::from(config["webhook_address"].as_str().unwrap_or("0.0.0.0:8080")),
+                                        secret);
+        server.run(|id| {
+            let details = match tc.get_artifact_details(id.to_string()) {
+                Some(details) => details,
+                None => {
+                    error!("Failed to get artifact {}, drop its webhook", id);
+                    return;
+                }
+            };
+            let tracker = match trackers.iter().find(|t| details["tracker"]["id"].as_u64() == Some(t.id)) {
+                Some(tracker) => tracker,
+                None => {
+                    info!("Ignore artifact {} from another tracker", id);
+                    return;
+                }
+            };
+            if !tracker.filter.matches(&details) {
+                info!("Ignore artifact {} not matching the filter", id);
+                return;
+            }
+            let artifacts = vec![details];
+            migrate_artifacts(&gc, &tracker.client, &tracker.retriever, &mut id_map, &mut report, &artifacts,
+                              true, 1, order, &*labels_group);
+            tracker.retriever.clean_files(&artifacts);
+            tc.save_manifest();
+            id_map.save();
+            report.save();
+        });
+        return;
+    }
+
+    // Artifacts are fetched, converted and posted batch by batch, so only one batch
+    // is in memory and on disk at a time
+    let batch_size = config["batch_size"].as_u64().unwrap_or(20);
+    for mut tracker in trackers {
+        info!("Migrate tracker {}", tracker.id);
+        let query = tracker.filter.query();
+        let mut offset = 0;
+        loop {
+            let artifacts = tracker.client.get_artifacts_page(offset, batch_size, &*query, tracker.filter.needs_values());
+            if artifacts.is_empty() {
+                break;
+            }
+            offset += artifacts.len() as u64;
+            let artifacts: Vec<Value> = artifacts.into_iter().filter(|a| {
+                tracker.filter.matches(a) && (!sync || id_map.get(a["id"].as_u64().unwrap_or(0)).is_none()
+                                              || modified_since(a, &id_map.last_run))
+            }).collect();
+            if artifacts.is_empty() {
+                continue;
+            }
+            info!("Get {} artifacts and build issues for gitlab", artifacts.len());
+            migrate_artifacts(&gc, &tracker.client, &tracker.retriever, &mut id_map, &mut report, &artifacts,
+                              sync, gitlab_workers, order, &*labels_group);
+            tracker.retriever.clean_files(&artifacts);
+            tc.save_manifest();
+            id_map.save();
+            report.save();
+        }
     }
+    id_map.last_run = Some(run_started);
+    id_map.save();
+    report.save();
 }
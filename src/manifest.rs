@@ -0,0 +1,89 @@
+use serde_json::{Value, from_str, to_string_pretty};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+
+/**
+ * Represent a downloaded tuleap file
+ */
+#[derive(Clone)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub sha256: String,
+    pub size: u64
+}
+
+/**
+ * Map tuleap file ids to the downloaded files, saved between runs
+ */
+pub struct Manifest {
+    path: String,
+    entries: HashMap<u64, ManifestEntry>
+}
+
+impl Manifest {
+    /**
+     * Load the manifest from a file
+     * @param path of the file
+     * @return the manifest, empty if the file doesn't exist
+     */
+    pub fn load(path: String) -> Manifest {
+        let mut manifest = Manifest {
+            path: path,
+            entries: HashMap::new()
+        };
+        let mut content = String::new();
+        match File::open(&*manifest.path) {
+            Ok(mut file) => { let _ = file.read_to_string(&mut content); },
+            Err(_) => return manifest
+        }
+        let json: Value = from_str(&*content).ok().expect("Incorrect manifest");
+        if let Some(files) = json["files"].as_array() {
+            for f in files {
+                manifest.entries.insert(f["tuleap_id"].as_u64().unwrap_or(0), ManifestEntry {
+                    path: String::from(f["path"].as_str().unwrap_or("")),
+                    sha256: String::from(f["sha256"].as_str().unwrap_or("")),
+                    size: f["size"].as_u64().unwrap_or(0)
+                });
+            }
+        }
+        manifest
+    }
+
+    /**
+     * Write the manifest to its file
+     */
+    pub fn save(&self) {
+        let mut ids: Vec<&u64> = self.entries.keys().collect();
+        ids.sort();
+        let files: Vec<Value> = ids.iter().map(|id| {
+            let e = &self.entries[id];
+            json!({
+                "tuleap_id": id,
+                "path": e.path,
+                "sha256": e.sha256,
+                "size": e.size
+            })
+        }).collect();
+        let json = json!({ "files": files });
+        let mut file = File::create(&*self.path).ok().expect("Failed to create manifest");
+        file.write_all(to_string_pretty(&json).unwrap().as_bytes()).ok().expect("Failed to write manifest");
+    }
+
+    /**
+     * @param tuleap_id the file id
+     * @return the downloaded file, if it was
+     */
+    pub fn get(&self, tuleap_id: u64) -> Option<&ManifestEntry> {
+        self.entries.get(&tuleap_id)
+    }
+
+    /**
+     * Store (or replace) a downloaded file
+     * @param tuleap_id the file id
+     * @param entry the local file
+     */
+    pub fn insert(&mut self, tuleap_id: u64, entry: ManifestEntry) {
+        self.entries.insert(tuleap_id, entry);
+    }
+}
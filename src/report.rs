@@ -0,0 +1,148 @@
+use chrono::prelude::*;
+use serde_json::{Value, to_string_pretty};
+use std::fs::File;
+use std::io::prelude::*;
+
+/**
+ * What happened to an artifact
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Created,
+    Updated,
+    Skipped,
+    Failed
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Outcome::Created => "created",
+            Outcome::Updated => "updated",
+            Outcome::Skipped => "skipped",
+            Outcome::Failed => "failed"
+        }
+    }
+}
+
+/**
+ * Represent the migration of one artifact
+ */
+pub struct ArtifactReport {
+    pub tuleap_id: u64,
+    pub title: String,
+    pub outcome: Outcome,
+    pub url: String,
+    pub related_urls: Vec<String>,
+    pub skipped_fields: Vec<String>,
+    pub unmapped: Vec<String>,
+    pub attachment_failures: Vec<String>,
+    pub failed_comments: usize,
+    pub retrieve_ms: u64,
+    pub post_ms: u64
+}
+
+/**
+ * Report of a migration, written as json and as a markdown summary
+ */
+pub struct Report {
+    path: String,
+    started_at: String,
+    artifacts: Vec<ArtifactReport>
+}
+
+impl Report {
+    /**
+     * @param path of the report, without extension
+     */
+    pub fn new(path: String) -> Report {
+        Report {
+            path: path,
+            started_at: Utc::now().to_rfc3339(),
+            artifacts: Vec::new()
+        }
+    }
+
+    /**
+     * Add an artifact to the report
+     * @param artifact the migrated artifact
+     */
+    pub fn add(&mut self, artifact: ArtifactReport) {
+        self.artifacts.push(artifact);
+    }
+
+    /**
+     * Write path.json and path.md
+     */
+    pub fn save(&self) {
+        let artifacts: Vec<Value> = self.artifacts.iter().map(|a| json!({
+            "tuleap_id": a.tuleap_id,
+            "title": a.title,
+            "outcome": a.outcome.as_str(),
+            "url": a.url,
+            "related_urls": a.related_urls,
+            "skipped_fields": a.skipped_fields,
+            "unmapped": a.unmapped,
+            "attachment_failures": a.attachment_failures,
+            "failed_comments": a.failed_comments,
+            "retrieve_ms": a.retrieve_ms,
+            "post_ms": a.post_ms
+        })).collect();
+        let json = json!({
+            "started_at": self.started_at,
+            "updated_at": Utc::now().to_rfc3339(),
+            "artifacts": artifacts
+        });
+        let mut file = File::create(format!("{}.json", self.path)).ok().expect("Failed to create report");
+        file.write_all(to_string_pretty(&json).unwrap().as_bytes()).ok().expect("Failed to write report");
+        let mut file = File::create(format!("{}.md", self.path)).ok().expect("Failed to create report");
+        file.write_all(self.markdown().as_bytes()).ok().expect("Failed to write report");
+    }
+
+    /**
+     * @return the summary page
+     */
+    fn markdown(&self) -> String {
+        let count = |outcome: Outcome| self.artifacts.iter().filter(|a| a.outcome == outcome).count();
+        let mut unmapped: Vec<&String> = self.artifacts.iter().flat_map(|a| a.unmapped.iter()).collect();
+        unmapped.sort();
+        unmapped.dedup();
+
+        let mut md = String::from("# Tuleap migration report\n\n");
+        md += &*format!("Started at {}\n\n", self.started_at);
+        md += &*format!("+ {} artifacts\n", self.artifacts.len());
+        md += &*format!("+ {} created\n", count(Outcome::Created));
+        md += &*format!("+ {} updated\n", count(Outcome::Updated));
+        md += &*format!("+ {} skipped\n", count(Outcome::Skipped));
+        md += &*format!("+ {} failed\n", count(Outcome::Failed));
+        md += &*format!("+ {} attachments failed\n",
+                        self.artifacts.iter().map(|a| a.attachment_failures.len()).sum::<usize>());
+        md += &*format!("+ {} comments failed\n",
+                        self.artifacts.iter().map(|a| a.failed_comments).sum::<usize>());
+        if !unmapped.is_empty() {
+            md += "\n## Unmapped\n\n";
+            for u in unmapped {
+                md += &*format!("+ {}\n", u);
+            }
+        }
+        md += "\n## Artifacts\n\n";
+        md += "| Artifact | Title | Outcome | Target | Skipped fields | Problems | Time (ms) |\n";
+        md += "|---|---|---|---|---|---|---|\n";
+        for a in self.artifacts.iter() {
+            let mut problems: Vec<String> = a.unmapped.iter().map(|u| format!("unmapped {}", u)).collect();
+            for f in a.attachment_failures.iter() {
+                problems.push(format!("attachment {} failed", f));
+            }
+            if a.failed_comments > 0 {
+                problems.push(format!("{} comments failed", a.failed_comments));
+            }
+            let mut targets = vec![a.url.clone()];
+            targets.extend(a.related_urls.iter().cloned());
+            md += &*format!("| {} | {} | {} | {} | {} | {} | {} |\n",
+                            a.tuleap_id, a.title.replace("|", "\\|"), a.outcome.as_str(), targets.join(" "),
+                            a.skipped_fields.join(", "), problems.join(", "),
+                            a.retrieve_ms + a.post_ms);
+        }
+        md
+    }
+}
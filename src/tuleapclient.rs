@@ -1,109 +1,524 @@
+use checksum::{sha256, sha256_file};
+use manifest::{Manifest, ManifestEntry};
 use reqwest;
 use serde_json::{Value, from_str};
+use std::collections::HashMap;
 use std::io::prelude::*;
-use std::fs::{File, create_dir, metadata};
+use std::fs::{File, OpenOptions, copy, create_dir_all, hard_link, metadata, remove_file, rename};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
+
+// Longest name of a downloaded file, in bytes
+const MAX_FILENAME_BYTES: usize = 240;
+
+/**
+ * Prefix of comments mirrored from gitlab, so they are never synced back
+ */
+pub const MIRROR_MARKER: &'static str = "[Mirrored from GitLab]";
+
+/**
+ * Use of the local cache of API responses
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum CacheMode {
+    // Always ask tuleap
+    Off,
+    // Ask tuleap and save each response
+    Record,
+    // Only use saved responses, tuleap is never reached
+    Replay
+}
 
 /**
  * Represent a tuleap client use to manipulate the API
+ * @note clones share the same rate limit, so a clone can be given to each worker
  */
+#[derive(Clone)]
 pub struct TuleapClient {
     client: reqwest::Client,
     tracker_url: String,
-    tracker_nb: u64
+    tracker_nb: u64,
+    max_retries: u32,
+    rate_limit: Duration,
+    last_request: Arc<Mutex<Instant>>,
+    access_key: String,
+    // Files already downloaded, by url
+    downloads: Arc<Mutex<HashMap<String, String>>>,
+    manifest: Arc<Mutex<Manifest>>,
+    cache_mode: CacheMode,
+    cache_dir: String,
+    // Tuleap does not support the expert query
+    query_refused: bool
 }
 
 impl TuleapClient {
-    pub fn new(tracker_url: String, tracker_nb: u64) -> TuleapClient {
+    /**
+     * @param tracker_url the base url of tuleap
+     * @param tracker_nb the tracker to migrate
+     * @param max_retries how many times a failed request is retried
+     * @param rate_limit_ms minimal delay between two requests (for all clones)
+     * @param access_key tuleap access key, needed to write (can be empty to only read public trackers)
+     * @param manifest where downloaded files are recorded
+     * @param cache_mode if API responses are recorded or replayed
+     * @param cache_dir where API responses are saved
+     */
+    pub fn new(tracker_url: String, tracker_nb: u64, max_retries: u32, rate_limit_ms: u64,
+               access_key: String, manifest: Manifest, cache_mode: CacheMode,
+               cache_dir: String) -> TuleapClient {
+        if cache_mode == CacheMode::Record {
+            create_dir_all(&*cache_dir).ok().expect("Failed to create cache directory");
+        }
         let client = reqwest::Client::new();
 
         TuleapClient {
             client: client,
             tracker_url: tracker_url,
-            tracker_nb: tracker_nb
+            tracker_nb: tracker_nb,
+            max_retries: max_retries,
+            rate_limit: Duration::from_millis(rate_limit_ms),
+            last_request: Arc::new(Mutex::new(Instant::now())),
+            access_key: access_key,
+            downloads: Arc::new(Mutex::new(HashMap::new())),
+            manifest: Arc::new(Mutex::new(manifest)),
+            cache_mode: cache_mode,
+            cache_dir: cache_dir,
+            query_refused: false
         }
     }
 
     /**
-     * Retrieve all artifacts from a tracker
-     * @return a vec of Json values from the API
+     * @return the base url of tuleap
      */
-    pub fn get_artifacts(&mut self) -> Vec<Value> {
-        let mut i = 0;
-        let mut finish = false;
-        let mut all_artifacts: Vec<Value> = Vec::new();
-        while !finish {
-            let url = format!("{}/api/trackers/{}/artifacts?offset={}", self.tracker_url, self.tracker_nb, i*100);
-            let mut req = self.client.get(&*url)
-                         .send().ok().expect("Failed to get artifacts");
-            let body = match req.text() {
-                Ok(body) => body,
-                Err(_) => String::from("")
+    pub fn tracker_url(&self) -> &str {
+        &*self.tracker_url
+    }
+
+    /**
+     * @param tracker_nb another tracker
+     * @return a client for this tracker, sharing the rate limit and downloaded files
+     */
+    pub fn with_tracker(&self, tracker_nb: u64) -> TuleapClient {
+        let mut client = self.clone();
+        client.tracker_nb = tracker_nb;
+        client.query_refused = false;
+        client
+    }
+
+    /**
+     * Wait until the rate limit allows a new request
+     */
+    fn wait_rate_limit(&self) {
+        let mut last_request = self.last_request.lock().unwrap();
+        let elapsed = last_request.elapsed();
+        if elapsed < self.rate_limit {
+            thread::sleep(self.rate_limit - elapsed);
+        }
+        *last_request = Instant::now();
+    }
+
+    /**
+     * Add the access key to a request if any
+     * @param builder the request
+     * @return the request with authentication
+     */
+    fn authenticate(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if self.access_key.is_empty() {
+            return builder;
+        }
+        builder.header("X-Auth-AccessKey", &*self.access_key)
+    }
+
+    /**
+     * GET an url, respecting the rate limit and retrying on failure
+     * @param url to get
+     * @return the response if any attempt succeeded
+     */
+    fn get(&self, url: &str) -> Option<reqwest::Response> {
+        if self.cache_mode == CacheMode::Replay {
+            warn!("{} is not requested while replaying", url);
+            return None;
+        }
+        let mut attempt = 0;
+        loop {
+            self.wait_rate_limit();
+            match self.authenticate(self.client.get(url)).send() {
+                Ok(ref res) if res.status().is_server_error() || res.status().as_u16() == 429 => {
+                    warn!("{} returned {}", url, res.status());
+                },
+                Ok(res) => return Some(res),
+                Err(e) => warn!("Failed to get {}: {}", url, e)
+            }
+            attempt += 1;
+            if attempt > self.max_retries {
+                return None;
+            }
+            thread::sleep(Duration::from_secs(1 << attempt.min(6)));
+        }
+    }
+
+    /**
+     * GET an API url, through the cache of responses
+     * @param url to get
+     * @return the body of the response
+     */
+    fn get_text(&self, url: &str) -> Option<String> {
+        // Named after the hash of the url, urls.txt gives the url of each file
+        let hash = sha256(url.as_bytes());
+        let cache_path = format!("{}/{}.json", self.cache_dir, hash);
+        if self.cache_mode == CacheMode::Replay {
+            let mut body = String::new();
+            return match File::open(&*cache_path).and_then(|mut f| f.read_to_string(&mut body)) {
+                Ok(_) => Some(body),
+                Err(_) => {
+                    warn!("{} is not in the cache", url);
+                    None
+                }
             };
-            let mut artifacts: Vec<Value> = from_str(&*body).ok().expect("Failed to parse artifacts");
-            finish = artifacts.is_empty();
-            all_artifacts.append(&mut artifacts);
-            i += 1;
         }
-        all_artifacts
+        let mut res = self.get(url)?;
+        let body = res.text().unwrap_or_default();
+        if self.cache_mode == CacheMode::Record && res.status().is_success() {
+            let indexed = metadata(&*cache_path).is_ok();
+            if let Err(e) = File::create(&*cache_path).and_then(|mut f| f.write_all(body.as_bytes())) {
+                warn!("Failed to record {}: {}", url, e);
+            } else if !indexed {
+                let index = format!("{}/urls.txt", self.cache_dir);
+                let line = format!("{}.json {}\n", hash, url);
+                if let Err(e) = OpenOptions::new().create(true).append(true).open(&*index)
+                    .and_then(|mut f| f.write_all(line.as_bytes())) {
+                    warn!("Failed to index {}: {}", url, e);
+                }
+            }
+        }
+        Some(body)
+    }
+
+    /**
+     * Retrieve a page of artifacts from a tracker
+     * @param offset index of the first artifact
+     * @param limit max number of artifacts to retrieve
+     * @param query tuleap expert query selecting artifacts, can be empty
+     * @param values if the values of the fields are needed
+     * @return a vec of Json values from the API, empty when there is no more artifacts
+     * @note if tuleap refuses the query, it is dropped for this page and the next ones
+     */
+    pub fn get_artifacts_page(&mut self, offset: u64, limit: u64, query: &str, values: bool) -> Vec<Value> {
+        let mut url = format!("{}/api/trackers/{}/artifacts?offset={}&limit={}",
+                              self.tracker_url, self.tracker_nb, offset, limit);
+        if values {
+            url += "&values=all";
+        }
+        if !query.is_empty() && !self.query_refused {
+            let encoded: String = form_urlencoded::byte_serialize(query.as_bytes()).collect();
+            // Not in the cache when tuleap refused it while recording
+            let body = self.get_text(&*format!("{}&expert_query={}", url, encoded)).unwrap_or_default();
+            match from_str::<Value>(&*body) {
+                Ok(Value::Array(artifacts)) => return artifacts,
+                _ => {
+                    warn!("Tuleap refused the query {}, artifacts are only filtered locally: {}", query, body);
+                    self.query_refused = true;
+                }
+            }
+        }
+        let body = self.get_text(&*url).expect("Failed to get artifacts");
+        from_str(&*body).ok().expect("Failed to parse artifacts")
+    }
+
+    /**
+     * Retrieve the trackers of a project
+     * @param project the id of the tuleap project
+     * @return a vec of Json values from the API
+     */
+    pub fn get_project_trackers(&mut self, project: u64) -> Vec<Value> {
+        let mut trackers: Vec<Value> = Vec::new();
+        loop {
+            let url = format!("{}/api/projects/{}/trackers?offset={}&limit=50", self.tracker_url, project, trackers.len());
+            let body = self.get_text(&*url).expect("Failed to get trackers");
+            let page: Vec<Value> = from_str(&*body).ok().expect("Failed to parse trackers");
+            let last = page.len() < 50;
+            trackers.extend(page);
+            if last {
+                return trackers;
+            }
+        }
+    }
+
+    /**
+     * Retrieve the structure of a tracker
+     * @param id the id of the tracker
+     * @return a Json value from the API, with the fields of the tracker
+     */
+    pub fn get_tracker(&mut self, id: u64) -> Value {
+        let url = format!("{}/api/trackers/{}", self.tracker_url, id);
+        let body = self.get_text(&*url).expect("Failed to get tracker");
+        from_str(&*body).ok().expect("Failed to parse tracker")
     }
 
     /**
      * Retrieve a detailled artifact from a tracker
      * @param id the id of the artifact
-     * @return a Json value from the API
+     * @return a Json value from the API, None if tuleap doesn't give it (like a deleted artifact)
      */
-    pub fn get_artifact_details(&mut self, id: String) -> Value {
+    pub fn get_artifact_details(&mut self, id: String) -> Option<Value> {
         let url = format!("{}/api/artifacts/{}", self.tracker_url, id);
-        let mut req = self.client.get(&*url)
-                     .send().ok().expect("Failed to get artifact's details");
-        let body = match req.text() {
-            Ok(body) => body,
-            Err(_) => String::from("")
-        };
-        from_str(&*body).ok().expect("Failed to parse artifact's details")
+        let details: Value = self.get_text(&*url).and_then(|body| from_str(&*body).ok())?;
+        if !details["error"].is_null() {
+            warn!("Failed to get artifact {}: {}", id, details["error"]);
+            return None;
+        }
+        Some(details)
     }
 
     /**
-     * Retrieve a file
-     * @param url the url of the file
-     * @param name the name of the file
-     * @param file_dir the base directory to save data
-     * @param id of the issue
+     * @param filename a file name from tuleap
+     * @return the name without path separators nor characters forbidden on common filesystems
+     */
+    fn safe_filename(filename: &str) -> String {
+        let mut name: String = filename.chars()
+            .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+            .collect();
+        // Names are limited to 255 bytes, with room for the .part suffix of downloads
+        if name.len() > MAX_FILENAME_BYTES {
+            let end = (0..MAX_FILENAME_BYTES + 1).rev().find(|&i| name.is_char_boundary(i)).unwrap_or(0);
+            name.truncate(end);
+        }
+        // No hidden file, nor . or ..
+        let name = name.trim_start_matches(|c: char| c == '.' || c.is_whitespace()).trim_end();
+        if name.is_empty() {
+            String::from("file")
+        } else {
+            String::from(name)
+        }
+    }
+
+    /**
+     * @param file_id tuleap id of the file
+     * @param size of the file given by tuleap, if known
+     * @return the cached file and its sha256 if it is still valid
+     */
+    fn cached_file(&self, file_id: u64, size: Option<u64>) -> Option<(String, String)> {
+        let entry = self.manifest.lock().unwrap().get(file_id)?.clone();
+        let valid = size.map_or(true, |s| s == entry.size)
+            && metadata(&*entry.path).map(|m| m.len() == entry.size).unwrap_or(false)
+            && sha256_file(&*entry.path).as_ref() == Some(&entry.sha256);
+        if !valid {
+            info!("cached file {} is outdated", entry.path);
+            return None;
+        }
+        Some((entry.path, entry.sha256))
+    }
+
+    /**
+     * Give a cached file its name in the directory of an artifact
+     * @param cache_path the cached file
+     * @param hash sha256 of the file
+     * @param dir of the artifact
+     * @param filename the name of the file
      * @return final path for the file
      */
-    pub fn get_file(&mut self, url: String, filename: String, file_dir: String, id: String) -> String {
-        let url = format!("{}{}", self.tracker_url, url);
-        let mut req = self.client.get(&*url)
-                     .send().ok().expect("Failed to get file");
-        let mut buf: Vec<u8> = vec![];
-        let _ = req.copy_to(&mut buf);
-        let _ = create_dir("data");
-        let _ = create_dir(format!("{}/{}",file_dir, id));
-        let mut final_path = format!("{}/{}/{}",file_dir, id, filename);
+    fn place_file(&self, cache_path: &str, hash: &str, dir: &str, filename: &str) -> Option<String> {
+        let mut final_path = format!("{}/{}", dir, filename);
         let mut i = 0;
         while metadata(final_path.clone()).is_ok() {
-            final_path = format!("{}/{}/{}{}",file_dir, id, i, filename);
+            // Same name and same content, this is the same file
+            if sha256_file(&*final_path).as_ref().map(|h| &**h) == Some(hash) {
+                debug!("file already exists: {}", final_path);
+                return Some(final_path);
+            }
+            final_path = format!("{}/{}{}", dir, i, filename);
             i += 1;
         }
         info!("create file: {}", final_path);
-        let mut buffer = File::create(final_path.clone()).ok().expect("Failed to create file");
-        buffer.write(buf.as_slice()).ok().expect("Failed to write buffer");
-        String::from(final_path)
+        if hard_link(cache_path, &*final_path).is_err() {
+            if let Err(e) = copy(cache_path, &*final_path) {
+                error!("Failed to create {}: {}", final_path, e);
+                return None;
+            }
+        }
+        Some(final_path)
     }
 
     /**
-     * Retrieve all comments from an artifact
-     * @param id the id of the artifact
-     * @return a vec of Json values from the API
+     * Retrieve a file
+     * @param url the url of the file
+     * @param file_id tuleap id of the file, if known
+     * @param size of the file given by tuleap, if known
+     * @param filename the name of the file
+     * @param file_dir the base directory to save data
+     * @param id of the issue
+     * @return final path for the file, None if it can't be downloaded
+     * @note files are cached in file_dir/cache by content and reused while their size and
+     * checksum match the manifest, a url is only downloaded once per run
      */
-    pub fn get_artifact_comments(&mut self, id: String) -> Vec<Value> {
-        let url = format!("{}/api/artifacts/{}/changesets?fields=comments", self.tracker_url, id);
-        let mut req = self.client.get(&*url)
-                     .send().ok().expect("Failed to get comments");
-        let body = match req.text() {
-            Ok(body) => body,
-            Err(_) => String::from("")
+    pub fn get_file(&mut self, url: String, file_id: Option<u64>, size: Option<u64>, filename: String,
+                    file_dir: String, id: String) -> Option<String> {
+        if let Some(path) = self.downloads.lock().unwrap().get(&url) {
+            if metadata(path).is_ok() {
+                debug!("file already downloaded: {}", path);
+                return Some(path.clone());
+            }
+        }
+        let filename = TuleapClient::safe_filename(&*filename);
+        let dir = format!("{}/{}", file_dir, id);
+        let cache_dir = format!("{}/cache", file_dir);
+        for d in vec![&dir, &cache_dir] {
+            if let Err(e) = create_dir_all(d) {
+                error!("Failed to create {}: {}", d, e);
+                return None;
+            }
+        }
+        let cached = file_id.and_then(|file_id| self.cached_file(file_id, size));
+        let (cache_path, hash) = match cached {
+            Some(cached) => {
+                debug!("file {} found in cache", url);
+                cached
+            },
+            None => {
+                let full_url = format!("{}{}", self.tracker_url, url);
+                let mut req = self.get(&*full_url)?;
+                // Stream to a temporary file, big files are never held in memory
+                let part_path = format!("{}/.{}.part", dir, filename);
+                {
+                    let mut part = match File::create(&*part_path) {
+                        Ok(part) => part,
+                        Err(e) => {
+                            error!("Failed to create {}: {}", part_path, e);
+                            return None;
+                        }
+                    };
+                    if let Err(e) = req.copy_to(&mut part) {
+                        warn!("Failed to download {}: {}", full_url, e);
+                        let _ = remove_file(&*part_path);
+                        return None;
+                    }
+                }
+                let hash = sha256_file(&*part_path)?;
+                let cache_path = format!("{}/{}", cache_dir, hash);
+                if metadata(&*cache_path).is_ok() {
+                    let _ = remove_file(&*part_path);
+                } else {
+                    if let Err(e) = rename(&*part_path, &*cache_path) {
+                        error!("Failed to create {}: {}", cache_path, e);
+                        return None;
+                    }
+                }
+                if let Some(file_id) = file_id {
+                    self.manifest.lock().unwrap().insert(file_id, ManifestEntry {
+                        path: cache_path.clone(),
+                        sha256: hash.clone(),
+                        size: metadata(&*cache_path).map(|m| m.len()).unwrap_or(0)
+                    });
+                }
+                (cache_path, hash)
+            }
         };
-        from_str(&*body).ok().expect("Failed to parse comments")
+        let final_path = self.place_file(&*cache_path, &*hash, &*dir, &*filename)?;
+        self.downloads.lock().unwrap().insert(url, final_path.clone());
+        Some(final_path)
+    }
+
+    /**
+     * Write the manifest of downloaded files
+     */
+    pub fn save_manifest(&self) {
+        self.manifest.lock().unwrap().save();
+    }
+
+    /**
+     * Retrieve all comments from an artifact, with the values of each changeset
+     * @param id the id of the artifact
+     * @return a vec of Json values from the API, None if tuleap doesn't give them
+     */
+    pub fn get_artifact_comments(&mut self, id: String) -> Option<Vec<Value>> {
+        let url = format!("{}/api/artifacts/{}/changesets?fields=all", self.tracker_url, id);
+        let comments = self.get_text(&*url).and_then(|body| from_str(&*body).ok());
+        if comments.is_none() {
+            warn!("Failed to get comments of artifact {}", id);
+        }
+        comments
+    }
+
+    /**
+     * Add a follow-up comment to an artifact
+     * @param id the id of the artifact
+     * @param body of the comment
+     * @return if the comment was posted
+     * @note not retried, to avoid posting the same comment twice
+     */
+    pub fn post_comment(&mut self, id: u64, body: String) -> bool {
+        let url = format!("{}/api/artifacts/{}", self.tracker_url, id);
+        let put = json!({
+            "values": [],
+            "comment": {
+                "body": body,
+                "format": "text"
+            }
+        });
+        self.wait_rate_limit();
+        match self.authenticate(self.client.put(&*url)).json(&put).send() {
+            Ok(res) => {
+                if !res.status().is_success() {
+                    warn!("Failed to comment artifact {}: {}", id, res.status());
+                }
+                res.status().is_success()
+            },
+            Err(e) => {
+                warn!("Failed to comment artifact {}: {}", id, e);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TuleapClient;
+
+    #[test]
+    fn safe_filename_keeps_plain_names() {
+        assert_eq!(TuleapClient::safe_filename("screenshot 1.png"), "screenshot 1.png");
+    }
+
+    #[test]
+    fn safe_filename_removes_traversal() {
+        assert_eq!(TuleapClient::safe_filename("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(TuleapClient::safe_filename("..\\..\\boot.ini"), "_.._boot.ini");
+        assert_eq!(TuleapClient::safe_filename("/etc/passwd"), "_etc_passwd");
+        assert_eq!(TuleapClient::safe_filename("C:\\log.txt"), "C__log.txt");
+    }
+
+    #[test]
+    fn safe_filename_removes_dot_names() {
+        assert_eq!(TuleapClient::safe_filename(".."), "file");
+        assert_eq!(TuleapClient::safe_filename("."), "file");
+        assert_eq!(TuleapClient::safe_filename(" . ."), "file");
+        assert_eq!(TuleapClient::safe_filename(" .bashrc"), "bashrc");
+    }
+
+    #[test]
+    fn safe_filename_names_empty_names() {
+        assert_eq!(TuleapClient::safe_filename(""), "file");
+        assert_eq!(TuleapClient::safe_filename("   "), "file");
+    }
+
+    #[test]
+    fn safe_filename_removes_forbidden_chars() {
+        assert_eq!(TuleapClient::safe_filename("a\nb*?\"<>|.txt"), "a_b______.txt");
+    }
+
+    #[test]
+    fn safe_filename_truncates_long_names() {
+        assert_eq!(TuleapClient::safe_filename(&*"a".repeat(300)).len(), 240);
+        // 2 bytes per char
+        let name = TuleapClient::safe_filename(&*"\u{e9}".repeat(200));
+        assert_eq!(name.len(), 240);
+        assert_eq!(name.chars().count(), 120);
+        // 3 bytes per char, cut at a char boundary
+        let name = TuleapClient::safe_filename(&*"\u{20ac}".repeat(100));
+        assert_eq!(name.len(), 240);
+        let name = TuleapClient::safe_filename(&*format!("a{}", "\u{20ac}".repeat(100)));
+        assert_eq!(name.len(), 238);
     }
 }
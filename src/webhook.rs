@@ -0,0 +1,313 @@
+use hmac::{Hmac, Mac};
+use serde_json::{Value, from_slice};
+use sha2::Sha256;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use url::form_urlencoded;
+
+// Bigger payloads are refused before being read
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+const MAX_HEADERS_SIZE: usize = 64 * 1024;
+// A connection sending nothing for this long is closed
+const TIMEOUT_SECS: u64 = 10;
+// A request must be received entirely in this time, even from a client sending slowly
+const DEADLINE_SECS: u64 = 30;
+// Connections being read at the same time, the others are refused
+const MAX_CONNECTIONS: usize = 16;
+
+/**
+ * Why a request could not be read
+ */
+enum RequestError {
+    BadRequest,
+    TooLarge,
+    Timeout
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> RequestError {
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => RequestError::Timeout,
+            _ => RequestError::BadRequest
+        }
+    }
+}
+
+/**
+ * Read a connection until a deadline
+ */
+struct DeadlineReader<'a> {
+    stream: &'a mut TcpStream,
+    deadline: Instant
+}
+
+impl<'a> Read for DeadlineReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        if now >= self.deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "request deadline reached"));
+        }
+        let idle = Duration::from_secs(TIMEOUT_SECS);
+        let left = self.deadline - now;
+        self.stream.set_read_timeout(Some(if left < idle { left } else { idle }))?;
+        self.stream.read(buf)
+    }
+}
+
+/**
+ * Http server receiving tuleap tracker webhooks
+ */
+pub struct WebhookServer {
+    address: String,
+    secret: String
+}
+
+impl WebhookServer {
+    /**
+     * @param address where to listen, like 0.0.0.0:8080
+     * @param secret used to sign payloads, requests without a valid signature are refused
+     * @note with an empty secret every request is refused, anybody could sign payloads
+     */
+    pub fn new(address: String, secret: String) -> WebhookServer {
+        WebhookServer {
+            address: address,
+            secret: secret
+        }
+    }
+
+    /**
+     * Listen forever and call handle for each artifact modified
+     * @param handle called with the artifact id, one artifact at a time in the order
+     * webhooks were received
+     */
+    pub fn run<F: FnMut(u64)>(&self, mut handle: F) {
+        let listener = TcpListener::bind(&*self.address).ok().expect("Failed to listen");
+        info!("Listening webhooks on {}", self.address);
+        let (tx, rx) = channel();
+        let secret = self.secret.clone();
+        let connections = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(mut stream) => {
+                        // Only this thread adds connections, so the count can't go over the limit
+                        if connections.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                            warn!("Refuse a webhook, {} connections are already open", MAX_CONNECTIONS);
+                            let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                            let _ = stream.write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n");
+                            continue;
+                        }
+                        connections.fetch_add(1, Ordering::SeqCst);
+                        // One thread per connection, so a slow client doesn't block the others
+                        let secret = secret.clone();
+                        let tx = tx.clone();
+                        let connections = connections.clone();
+                        thread::spawn(move || {
+                            WebhookServer::receive(stream, &*secret, &tx);
+                            connections.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    },
+                    Err(e) => warn!("Failed connection: {}", e)
+                }
+            }
+        });
+        // Bursts are queued here and processed in order
+        for id in rx {
+            info!("Apply webhook for artifact {}", id);
+            handle(id);
+        }
+    }
+
+    /**
+     * Read a request, check its signature and queue the artifact
+     * @param stream the connection
+     * @param secret to check the signature
+     * @param queue where to send the artifact id
+     */
+    fn receive(mut stream: TcpStream, secret: &str, queue: &Sender<u64>) {
+        let timeout = Some(Duration::from_secs(TIMEOUT_SECS));
+        if stream.set_read_timeout(timeout).is_err() || stream.set_write_timeout(timeout).is_err() {
+            return;
+        }
+        let (signature, body) = match WebhookServer::read_request(&mut stream) {
+            Ok(request) => request,
+            Err(RequestError::TooLarge) => {
+                warn!("Refuse a webhook bigger than {} bytes", MAX_BODY_SIZE);
+                let _ = stream.write_all(b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\n\r\n");
+                return;
+            },
+            Err(RequestError::BadRequest) => {
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+                return;
+            },
+            Err(RequestError::Timeout) => {
+                warn!("Drop a webhook not received in time");
+                let _ = stream.write_all(b"HTTP/1.1 408 Request Timeout\r\nContent-Length: 0\r\n\r\n");
+                return;
+            }
+        };
+        if !WebhookServer::check_signature(secret, &*signature, &*body) {
+            warn!("Refuse webhook with an invalid signature");
+            let _ = stream.write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n");
+            return;
+        }
+        match WebhookServer::artifact_id(&*body) {
+            Some(id) => {
+                let _ = queue.send(id);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+            },
+            None => {
+                warn!("No artifact in webhook");
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+            }
+        }
+    }
+
+    /**
+     * Parse a POST request
+     * @param stream the connection
+     * @return the signature header and the body
+     */
+    fn read_request(stream: &mut TcpStream) -> Result<(String, Vec<u8>), RequestError> {
+        // Never read more than a request can hold, nor for longer than the deadline
+        let deadline = DeadlineReader {
+            stream: stream,
+            deadline: Instant::now() + Duration::from_secs(DEADLINE_SECS)
+        };
+        let mut reader = BufReader::new(deadline.take((MAX_HEADERS_SIZE + MAX_BODY_SIZE) as u64));
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("POST ") {
+            return Err(RequestError::BadRequest);
+        }
+        let mut length = 0;
+        let mut signature = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(RequestError::BadRequest);
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let mut parts = header.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim().to_lowercase();
+            let value = parts.next().unwrap_or("").trim();
+            if name == "content-length" {
+                length = value.parse().map_err(|_| RequestError::BadRequest)?;
+            } else if name == "x-tuleap-webhook-signature" {
+                signature = String::from(value);
+            }
+        }
+        if length > MAX_BODY_SIZE {
+            return Err(RequestError::TooLarge);
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        Ok((signature, body))
+    }
+
+    /**
+     * @param secret shared with tuleap
+     * @param signature hex HMAC-SHA256 of the body
+     * @param body of the request
+     * @return if the signature is valid
+     */
+    fn check_signature(secret: &str, signature: &str, body: &[u8]) -> bool {
+        if secret.is_empty() || signature.is_empty() || !signature.is_ascii() || signature.len() % 2 != 0 {
+            return false;
+        }
+        let signature: Option<Vec<u8>> = (0..signature.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).ok())
+            .collect();
+        let signature = match signature {
+            Some(signature) => signature,
+            None => return false
+        };
+        let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+            Ok(mac) => mac,
+            Err(_) => return false
+        };
+        mac.update(body);
+        mac.verify_slice(&*signature).is_ok()
+    }
+
+    /**
+     * Extract the artifact from a payload, sent as json or as a payload form field
+     * @param body of the request
+     * @return the artifact id
+     */
+    fn artifact_id(body: &[u8]) -> Option<u64> {
+        let payload: Value = match from_slice(body) {
+            Ok(payload) => payload,
+            Err(_) => {
+                let (_, payload) = form_urlencoded::parse(body).find(|&(ref k, _)| k == "payload")?;
+                from_slice(payload.as_bytes()).ok()?
+            }
+        };
+        payload["current"]["id"].as_u64().or(payload["id"].as_u64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WebhookServer;
+
+    // RFC 4231, test case 2
+    const SECRET: &'static str = "Jefe";
+    const BODY: &'static [u8] = b"what do ya want for nothing?";
+    const SIGNATURE: &'static str = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+
+    #[test]
+    fn check_signature_accepts_valid_signature() {
+        assert!(WebhookServer::check_signature(SECRET, SIGNATURE, BODY));
+        assert!(WebhookServer::check_signature(SECRET, &*SIGNATURE.to_uppercase(), BODY));
+    }
+
+    #[test]
+    fn check_signature_refuses_tampered_requests() {
+        assert!(!WebhookServer::check_signature(SECRET, SIGNATURE, b"what do ya want for something?"));
+        assert!(!WebhookServer::check_signature("Jeff", SIGNATURE, BODY));
+        let tampered = format!("{}4", &SIGNATURE[..SIGNATURE.len() - 1]);
+        assert!(!WebhookServer::check_signature(SECRET, &*tampered, BODY));
+    }
+
+    #[test]
+    fn check_signature_refuses_malformed_signatures() {
+        assert!(!WebhookServer::check_signature(SECRET, "", BODY));
+        assert!(!WebhookServer::check_signature("", SIGNATURE, BODY));
+        assert!(!WebhookServer::check_signature(SECRET, &SIGNATURE[1..], BODY));
+        assert!(!WebhookServer::check_signature(SECRET, "zz", BODY));
+        assert!(!WebhookServer::check_signature(SECRET, "a\u{e9}b", BODY));
+        assert!(!WebhookServer::check_signature(SECRET, &SIGNATURE[..32], BODY));
+    }
+
+    #[test]
+    fn artifact_id_from_json() {
+        assert_eq!(WebhookServer::artifact_id(br#"{"id": 42, "current": {"id": 42}}"#), Some(42));
+        assert_eq!(WebhookServer::artifact_id(br#"{"id": 42}"#), Some(42));
+        assert_eq!(WebhookServer::artifact_id(br#"{"current": {"id": 43}}"#), Some(43));
+    }
+
+    #[test]
+    fn artifact_id_from_form() {
+        assert_eq!(WebhookServer::artifact_id(b"payload=%7B%22id%22%3A42%7D"), Some(42));
+        assert_eq!(WebhookServer::artifact_id(b"other=1&payload=%7B%22current%22%3A%7B%22id%22%3A7%7D%7D"), Some(7));
+    }
+
+    #[test]
+    fn artifact_id_refuses_invalid_payloads() {
+        assert_eq!(WebhookServer::artifact_id(b""), None);
+        assert_eq!(WebhookServer::artifact_id(b"payload=not%20json"), None);
+        assert_eq!(WebhookServer::artifact_id(br#"{"id": "42"}"#), None);
+        assert_eq!(WebhookServer::artifact_id(b"id=42"), None);
+    }
+}